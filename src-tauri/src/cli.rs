@@ -0,0 +1,410 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::config::{ClientConfig, ConfigError};
+use crate::download::{Artifact, CancelToken};
+use crate::error::Error;
+use crate::events::StdoutSink;
+use crate::install::{self, InstallManifest};
+use crate::paths::{self, Paths};
+use crate::service::{self, ServiceKind};
+use crate::setup::SetupContext;
+use crate::{http, logger, migrations, server};
+
+const USAGE: &str = "Usage: lobe_chat [COMMAND]
+
+Without a command the desktop window is opened.
+
+Commands:
+  install          Download and install deno and LobeChat
+  serve [--port N] Run the LobeChat server in the foreground
+  status           Show installed versions and whether the server is running
+  stop             Stop the server started by `serve`
+  update           Install the versions set in config.toml
+  service install [--xdg]
+                   Run the server at login with a systemd user unit,
+                   or an XDG autostart entry when systemd is unavailable
+  service uninstall
+                   Remove the files written by `service install`
+  help             Print this message";
+
+/** 不创建窗口的子命令 */
+#[derive(Debug, PartialEq)]
+enum Subcommand {
+    Install,
+    Serve { port: Option<u16> },
+    Status,
+    Stop,
+    Update,
+    ServiceInstall { xdg: bool },
+    ServiceUninstall,
+    Help,
+}
+
+/** 解析子命令,第一个参数不是子命令时返回 None,交给窗口处理 */
+fn parse(args: &[String]) -> Result<Option<Subcommand>, String> {
+    let (name, rest) = match args.split_first() {
+        Some((name, rest)) => (name.as_str(), rest),
+        None => return Ok(None),
+    };
+    let command = match name {
+        "install" => Subcommand::Install,
+        "serve" => {
+            let mut port = None;
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                match arg.as_str() {
+                    "--port" => {
+                        let value = rest.next().ok_or("--port requires a value")?;
+                        port = Some(
+                            value
+                                .parse::<u16>()
+                                .ok()
+                                .filter(|port| *port != 0)
+                                .ok_or_else(|| format!("`{}` is not a valid port", value))?,
+                        );
+                    }
+                    arg => return Err(format!("unexpected argument `{}`", arg)),
+                }
+            }
+            return Ok(Some(Subcommand::Serve { port }));
+        }
+        "status" => Subcommand::Status,
+        "stop" => Subcommand::Stop,
+        "update" => Subcommand::Update,
+        "service" => match rest.split_first() {
+            Some((action, flags)) if action == "install" => {
+                let xdg = match flags {
+                    [] => false,
+                    [flag] if flag == "--xdg" => true,
+                    [arg, ..] => return Err(format!("unexpected argument `{}`", arg)),
+                };
+                return Ok(Some(Subcommand::ServiceInstall { xdg }));
+            }
+            Some((action, flags)) if action == "uninstall" => {
+                if let Some(arg) = flags.first() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                return Ok(Some(Subcommand::ServiceUninstall));
+            }
+            Some((action, _)) => return Err(format!("unknown service action `{}`", action)),
+            None => return Err(String::from("service requires `install` or `uninstall`")),
+        },
+        "help" | "--help" | "-h" => Subcommand::Help,
+        _ => return Ok(None),
+    };
+    if let Some(arg) = rest.first() {
+        return Err(format!("unexpected argument `{}`", arg));
+    }
+    Ok(Some(command))
+}
+
+/**
+ * 命令行入口
+ * 传入子命令时不创建窗口,执行完成后返回退出码,没有子命令时返回 None
+ */
+pub fn run(args: &[String]) -> Option<i32> {
+    let command = match parse(args) {
+        Ok(Some(command)) => command,
+        Ok(None) => return None,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return Some(2);
+        }
+    };
+    if command == Subcommand::Help {
+        println!("{}", USAGE);
+        return Some(0);
+    }
+    let r = load_context().and_then(|ctx| match command {
+        Subcommand::Install => block_on(install(&ctx, false)),
+        Subcommand::Update => block_on(update(&ctx)),
+        Subcommand::Serve { port } => serve(&ctx, port),
+        Subcommand::Status => status(&ctx),
+        Subcommand::Stop => stop(&ctx),
+        Subcommand::ServiceInstall { xdg } => service_install(&ctx, xdg),
+        Subcommand::ServiceUninstall => service_uninstall(),
+        Subcommand::Help => Ok(0),
+    });
+    match r {
+        Ok(code) => Some(code),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            Some(1)
+        }
+    }
+}
+
+fn block_on<F>(future: F) -> Result<i32, Error>
+where
+    F: std::future::Future<Output = Result<i32, Error>>,
+{
+    tokio::runtime::Runtime::new()?.block_on(future)
+}
+
+/** 与窗口使用相同的应用目录和配置 */
+fn load_context() -> Result<SetupContext, Error> {
+    let app_dir = paths::default_app_dir();
+    fs::create_dir_all(&app_dir)?;
+    migrations::migrate_config(&app_dir).map_err(ConfigError::from)?;
+    let config = ClientConfig::load(&app_dir)?;
+    logger::init(&config.log.level);
+    let http_client = http::build_client(&config.http)?;
+    let os_target = paths::os_target();
+    Ok(SetupContext::new(
+        Paths::new(&app_dir, os_target, &config),
+        os_target,
+        config,
+        http_client,
+        Arc::new(StdoutSink),
+    ))
+}
+
+/** 安装缺少的 deno 和 LobeChat,force_deno 为 true 时重新安装 deno */
+async fn install(ctx: &SetupContext, force_deno: bool) -> Result<i32, Error> {
+    let token = CancelToken::default();
+    if force_deno
+        || !install::check_deno(&ctx.paths.app_dir, &ctx.paths.deno_bin, &ctx.deno.version)
+    {
+        ctx.install_deno(&token).await?;
+        println!(
+            "deno {} installed at {}",
+            ctx.deno.version,
+            ctx.paths.deno_bin.display()
+        );
+    } else {
+        println!(
+            "deno is already installed at {}",
+            ctx.paths.deno_bin.display()
+        );
+    }
+    if !install::check_lobe_chat(
+        &ctx.paths.app_dir,
+        &ctx.paths.lobe_chat_dir,
+        &ctx.lobe_chat.version,
+    ) {
+        ctx.install_lobe_chat(&token).await?;
+        println!(
+            "LobeChat {} installed at {}",
+            ctx.lobe_chat.version,
+            ctx.paths.lobe_chat_dir.display()
+        );
+    } else {
+        println!(
+            "LobeChat {} is already installed at {}",
+            ctx.lobe_chat.version,
+            ctx.paths.lobe_chat_dir.display()
+        );
+    }
+    Ok(0)
+}
+
+/**
+ * 安装 config.toml 中设置的版本
+ * LobeChat 每个版本使用单独的目录,deno 只在由本应用安装且版本不同时重新下载
+ */
+async fn update(ctx: &SetupContext) -> Result<i32, Error> {
+    let deno_outdated = InstallManifest::read(&ctx.paths.app_dir, Artifact::Deno)
+        .map(|manifest| manifest.sha256.is_some() && manifest.version != ctx.deno.version)
+        .unwrap_or(false);
+    if deno_outdated {
+        // 压缩包的文件名不包含版本号,需要重新下载
+        let _ = fs::remove_file(&ctx.paths.deno_archive);
+    }
+    install(ctx, deno_outdated).await?;
+    // 服务文件中记录了 LobeChat 的目录,版本变化后需要更新
+    if let Some(kind) = service::refresh(&ctx.paths, &ctx.config)? {
+        println!("{} updated", kind.path()?.display());
+    }
+    if let Some(pid) = server::running_pid(&ctx.paths.app_dir) {
+        println!(
+            "server is running with pid {}, restart it to use the new version",
+            pid
+        );
+    }
+    Ok(0)
+}
+
+/** 在前台运行服务,服务退出后返回它的退出码 */
+fn serve(ctx: &SetupContext, port: Option<u16>) -> Result<i32, Error> {
+    if let Some(pid) = server::running_pid(&ctx.paths.app_dir) {
+        return Err(Error::Process(format!(
+            "server is already running with pid {}",
+            pid
+        )));
+    }
+    if !install::check_deno(&ctx.paths.app_dir, &ctx.paths.deno_bin, &ctx.deno.version)
+        || !install::check_lobe_chat(
+            &ctx.paths.app_dir,
+            &ctx.paths.lobe_chat_dir,
+            &ctx.lobe_chat.version,
+        )
+    {
+        eprintln!("LobeChat is not installed, run `lobe_chat install` first");
+        return Ok(1);
+    }
+    let port = port.unwrap_or(ctx.config.server.port);
+    let mut command = server::command(&ctx.paths.deno_bin, &ctx.paths.lobe_chat_dir, port);
+    if let Some(env_file) = ctx.config.server.env_file.as_ref() {
+        command.envs(server::read_env_file(env_file)?);
+    }
+    let mut child = command.spawn().map_err(|err| {
+        Error::Process(format!(
+            "failed to start {}: {}",
+            ctx.paths.deno_bin.display(),
+            err
+        ))
+    })?;
+    server::write_pid(&ctx.paths.app_dir, child.id())?;
+    println!(
+        "LobeChat server started with pid {}, listening on {}",
+        child.id(),
+        server::url(port)
+    );
+    let status = child.wait();
+    server::remove_pid(&ctx.paths.app_dir);
+    Ok(status?.code().unwrap_or(1))
+}
+
+fn status(ctx: &SetupContext) -> Result<i32, Error> {
+    println!("app dir:  {}", ctx.paths.app_dir.display());
+    match InstallManifest::read(&ctx.paths.app_dir, Artifact::Deno) {
+        Some(manifest) if ctx.paths.deno_bin.is_file() => {
+            println!(
+                "deno:     {} ({})",
+                manifest.version,
+                ctx.paths.deno_bin.display()
+            )
+        }
+        _ => println!("deno:     not installed"),
+    }
+    match InstallManifest::read(&ctx.paths.app_dir, Artifact::LobeChat) {
+        Some(manifest) if install::validate_lobe_chat(&ctx.paths.lobe_chat_dir).is_ok() => {
+            println!(
+                "LobeChat: {} ({})",
+                manifest.version,
+                ctx.paths.lobe_chat_dir.display()
+            )
+        }
+        _ => println!("LobeChat: {} not installed", ctx.lobe_chat.version),
+    }
+    match server::running_pid(&ctx.paths.app_dir) {
+        Some(pid) => println!(
+            "server:   running with pid {}, port {}",
+            pid, ctx.config.server.port
+        ),
+        None => println!("server:   stopped"),
+    }
+    Ok(0)
+}
+
+fn stop(ctx: &SetupContext) -> Result<i32, Error> {
+    match server::running_pid(&ctx.paths.app_dir) {
+        Some(pid) => {
+            server::stop(pid)?;
+            server::remove_pid(&ctx.paths.app_dir);
+            println!("server with pid {} stopped", pid);
+        }
+        None => println!("server is not running"),
+    }
+    Ok(0)
+}
+
+fn service_install(ctx: &SetupContext, xdg: bool) -> Result<i32, Error> {
+    let kind = service::install(&ctx.paths, &ctx.config, xdg)?;
+    let path = kind.path()?;
+    match kind {
+        ServiceKind::Systemd => println!(
+            "{} written and enabled, check it with `systemctl --user status {}`",
+            path.display(),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        ServiceKind::XdgAutostart => println!(
+            "{} written, the server will start at next login",
+            path.display()
+        ),
+    }
+    if !install::check_deno(&ctx.paths.app_dir, &ctx.paths.deno_bin, &ctx.deno.version)
+        || !install::check_lobe_chat(
+            &ctx.paths.app_dir,
+            &ctx.paths.lobe_chat_dir,
+            &ctx.lobe_chat.version,
+        )
+    {
+        println!("LobeChat is not installed yet, run `lobe_chat install` before it starts");
+    }
+    Ok(0)
+}
+
+fn service_uninstall() -> Result<i32, Error> {
+    let removed = service::uninstall()?;
+    if removed.is_empty() {
+        println!("no service is installed");
+    }
+    for path in removed {
+        println!("{} removed", path.display());
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Subcommand};
+
+    fn parse_args(args: &[&str]) -> Result<Option<Subcommand>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse(&args)
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(parse_args(&["install"]), Ok(Some(Subcommand::Install)));
+        assert_eq!(parse_args(&["status"]), Ok(Some(Subcommand::Status)));
+        assert_eq!(parse_args(&["stop"]), Ok(Some(Subcommand::Stop)));
+        assert_eq!(parse_args(&["update"]), Ok(Some(Subcommand::Update)));
+        assert_eq!(parse_args(&["--help"]), Ok(Some(Subcommand::Help)));
+        assert!(parse_args(&["status", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn leaves_other_arguments_to_the_window() {
+        assert_eq!(parse_args(&[]), Ok(None));
+        assert_eq!(parse_args(&["--hidden"]), Ok(None));
+        assert_eq!(parse_args(&["lobechat://chat?session=inbox"]), Ok(None));
+    }
+
+    #[test]
+    fn parses_serve_port() {
+        assert_eq!(
+            parse_args(&["serve"]),
+            Ok(Some(Subcommand::Serve { port: None }))
+        );
+        assert_eq!(
+            parse_args(&["serve", "--port", "3210"]),
+            Ok(Some(Subcommand::Serve { port: Some(3210) }))
+        );
+        assert!(parse_args(&["serve", "--port"]).is_err());
+        assert!(parse_args(&["serve", "--port", "0"]).is_err());
+        assert!(parse_args(&["serve", "--port", "65536"]).is_err());
+        assert!(parse_args(&["serve", "--host", "0.0.0.0"]).is_err());
+    }
+
+    #[test]
+    fn parses_service_actions() {
+        assert_eq!(
+            parse_args(&["service", "install"]),
+            Ok(Some(Subcommand::ServiceInstall { xdg: false }))
+        );
+        assert_eq!(
+            parse_args(&["service", "install", "--xdg"]),
+            Ok(Some(Subcommand::ServiceInstall { xdg: true }))
+        );
+        assert_eq!(
+            parse_args(&["service", "uninstall"]),
+            Ok(Some(Subcommand::ServiceUninstall))
+        );
+        assert!(parse_args(&["service"]).is_err());
+        assert!(parse_args(&["service", "restart"]).is_err());
+        assert!(parse_args(&["service", "uninstall", "--xdg"]).is_err());
+    }
+}
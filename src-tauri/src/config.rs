@@ -0,0 +1,874 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::migrations::MigrationError;
+
+/** 当前配置文件的版本 */
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+/** 配置文件名,位于应用目录下 */
+pub const CONFIG_FILENAME: &str = "config.toml";
+/** 环境变量覆盖配置时使用的前缀 */
+pub const ENV_PREFIX: &str = "LOBE_CLIENT_";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{path}:{line}:{column}: {message}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("invalid value for `{key}`: {message}")]
+    Invalid { key: String, message: String },
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DenoConfig {
+    /** 下载的 deno 版本 */
+    pub version: String,
+    /** deno release 下载地址前缀 */
+    pub url_prefix: String,
+    /** deno 安装目录,默认 ~/.deno */
+    pub install_dir: Option<PathBuf>,
+    /** 完整的下载地址,设置后不再根据 version 和 url_prefix 拼接,支持 zip、tar.gz、tar.xz、tar.zst */
+    pub url: Option<String>,
+    /** 压缩包的 sha256,设置后下载完成时校验,不一致时删除压缩包,修改 version 或 url 时需要一起修改 */
+    pub sha256: Option<String>,
+}
+impl Default for DenoConfig {
+    fn default() -> Self {
+        Self {
+            version: String::from("2.1.4"),
+            url_prefix: String::from("https://github.com/denoland/deno/releases/download"),
+            install_dir: None,
+            url: None,
+            sha256: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LobeChatConfig {
+    /** LobeChat 打包产物版本 */
+    pub version: String,
+    /** 打包产物下载地址前缀,实际地址为 {url_prefix}/{version}/lobe-chat_{version}.zip */
+    pub url_prefix: String,
+    /** 完整的下载地址,设置后不再根据 version 和 url_prefix 拼接,支持 zip、tar.gz、tar.xz、tar.zst */
+    pub url: Option<String>,
+    /** 打包产物的 sha256,同 deno.sha256 */
+    pub sha256: Option<String>,
+}
+impl Default for LobeChatConfig {
+    fn default() -> Self {
+        Self {
+            version: String::from("v1.36.11"),
+            url_prefix: String::from("https://github.com/ltaoo/LobeChatClient/releases/download"),
+            url: None,
+            sha256: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    /** github 加速地址,拼接在下载地址前面,空字符串表示直连 */
+    pub mirrors: Vec<String>,
+    /** 取消下载后是否保留已下载的部分,下次从中断处继续 */
+    pub keep_partial: bool,
+    pub retry: RetryConfig,
+}
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            mirrors: vec![String::from("https://ghgo.xyz/")],
+            keep_partial: true,
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+/**
+ * 下载失败后的重试策略
+ * 第 n 次重试前等待 [0, min(initial_delay_ms * 2^(n-1), max_delay_ms)] 之间的随机时间
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /** 最多重试次数,0 表示不重试 */
+    pub max_retries: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /** 代理地址,支持 http://、https://、socks5://,不设置时使用 HTTP_PROXY、HTTPS_PROXY 等环境变量 */
+    pub proxy: Option<String>,
+    /** 额外信任的 CA 证书,PEM 格式,可以包含多个证书 */
+    pub ca_bundle: Option<PathBuf>,
+    /** 建立连接的超时时间,单位秒 */
+    pub connect_timeout_secs: u64,
+    /** 两次读取数据之间的超时时间,单位秒 */
+    pub read_timeout_secs: u64,
+    /** 下载速度持续低于 stall_min_speed 超过该时间时视为下载停滞,单位秒 */
+    pub stall_timeout_secs: u64,
+    /** 判断下载停滞的最低速度,字节/秒 */
+    pub stall_min_speed: u64,
+    /** 请求使用的 User-Agent,不设置时为 LobeChatClient/{版本号} */
+    pub user_agent: Option<String>,
+}
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            ca_bundle: None,
+            connect_timeout_secs: 15,
+            read_timeout_secs: 30,
+            stall_timeout_secs: 60,
+            stall_min_speed: 1024,
+            user_agent: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /** LobeChat 服务监听的端口 */
+    pub port: u16,
+    /** 启动服务时读取的环境变量文件,每行一个 KEY=VALUE,用于设置 OPENAI_API_KEY 等 */
+    pub env_file: Option<PathBuf>,
+}
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: 6188,
+            env_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PtyConfig {
+    /** 终端使用的 shell,不设置时使用 $SHELL */
+    pub shell: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StartupConfig {
+    /** 登录系统后自动启动客户端,此时不显示窗口,只在后台启动服务 */
+    pub autostart: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /**
+     * 主窗口中允许打开的其他地址,例如登录时跳转的 https://github.com/login
+     * 本地服务之外的地址默认用系统浏览器打开
+     */
+    pub navigation_allowlist: Vec<String>,
+    /** 关闭主窗口时隐藏而不是退出,服务继续运行,通过托盘图标、Dock 图标或再次打开客户端显示 */
+    pub hide_on_close: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /** 日志级别 error、warn、info、debug、trace */
+    pub level: String,
+}
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+        }
+    }
+}
+
+/**
+ * 客户端配置
+ * 对应应用目录下的 config.toml,缺省的字段使用默认值
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /** 配置文件版本 */
+    pub schema_version: u32,
+    pub deno: DenoConfig,
+    pub lobe_chat: LobeChatConfig,
+    pub download: DownloadConfig,
+    pub http: HttpConfig,
+    pub server: ServerConfig,
+    pub pty: PtyConfig,
+    pub startup: StartupConfig,
+    pub window: WindowConfig,
+    pub log: LogConfig,
+}
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            schema_version: CONFIG_SCHEMA_VERSION,
+            deno: DenoConfig::default(),
+            lobe_chat: LobeChatConfig::default(),
+            download: DownloadConfig::default(),
+            http: HttpConfig::default(),
+            server: ServerConfig::default(),
+            pty: PtyConfig::default(),
+            startup: StartupConfig::default(),
+            window: WindowConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl ClientConfig {
+    /**
+     * 读取应用目录下的 config.toml,不存在时写入默认配置
+     * 读取后应用环境变量覆盖并校验
+     */
+    pub fn load(app_dir: &Path) -> Result<Self, ConfigError> {
+        let mut config = Self::read_file(app_dir)?;
+        config.apply_env_overrides(env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /** 只读取 config.toml 本身,不应用环境变量,修改设置时以此为基础 */
+    pub fn read_file(app_dir: &Path) -> Result<Self, ConfigError> {
+        let path = app_dir.join(CONFIG_FILENAME);
+        if !path.exists() {
+            let config = Self::default();
+            config.save(app_dir)?;
+            return Ok(config);
+        }
+        let content = fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        Self::parse(&content, &path)
+    }
+
+    /** 解析配置内容,出错时给出行号和列号 */
+    pub fn parse(content: &str, path: &Path) -> Result<Self, ConfigError> {
+        toml::from_str::<Self>(content).map_err(|err| {
+            let (line, column) = err
+                .span()
+                .map(|span| line_column(content, span.start))
+                .unwrap_or((0, 0));
+            ConfigError::Parse {
+                path: path.to_path_buf(),
+                line,
+                column,
+                message: err.message().to_string(),
+            }
+        })
+    }
+
+    pub fn save(&self, app_dir: &Path) -> Result<(), ConfigError> {
+        let path = app_dir.join(CONFIG_FILENAME);
+        let content = toml::to_string_pretty(self).map_err(|err| ConfigError::Invalid {
+            key: String::from("*"),
+            message: err.to_string(),
+        })?;
+        write_atomic(&path, content.as_bytes()).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })
+    }
+
+    /** 与另一份配置相比发生变化的字段,如 server.port */
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut changed = vec![];
+        if let (Ok(before), Ok(after)) = (serde_json::to_value(self), serde_json::to_value(other)) {
+            diff_keys(&before, &after, "", &mut changed);
+        }
+        changed
+    }
+
+    /**
+     * 将 JSON 格式的部分设置合并到当前配置,返回新配置以及发生变化的字段
+     * 合并规则同 RFC 7386,值为 null 表示恢复默认值
+     */
+    pub fn apply_patch(&self, patch: &Value) -> Result<(Self, Vec<String>), ConfigError> {
+        if !patch.is_object() {
+            return Err(ConfigError::Invalid {
+                key: String::from("*"),
+                message: String::from("settings patch must be an object"),
+            });
+        }
+        let mut merged = serde_json::to_value(self).map_err(|err| ConfigError::Invalid {
+            key: String::from("*"),
+            message: err.to_string(),
+        })?;
+        merge_patch(&mut merged, patch);
+        let next: Self = serde_json::from_value(merged).map_err(|err| ConfigError::Invalid {
+            key: String::from("*"),
+            message: err.to_string(),
+        })?;
+        next.validate()?;
+        let changed = self.diff(&next);
+        if changed.iter().any(|key| key == "schema_version") {
+            return Err(ConfigError::Invalid {
+                key: String::from("schema_version"),
+                message: String::from("is read only"),
+            });
+        }
+        Ok((next, changed))
+    }
+
+    /**
+     * 使用 LOBE_CLIENT_* 环境变量覆盖配置
+     * 如 LOBE_CLIENT_SERVER_PORT=3210、LOBE_CLIENT_DOWNLOAD_MIRRORS=https://a/,https://b/
+     */
+    pub fn apply_env_overrides<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            let key = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) => key,
+                None => continue,
+            };
+            match key {
+                "DENO_VERSION" => self.deno.version = value,
+                "DENO_URL_PREFIX" => self.deno.url_prefix = value,
+                "DENO_URL" => self.deno.url = Some(value),
+                "DENO_INSTALL_DIR" => self.deno.install_dir = Some(PathBuf::from(value)),
+                "DENO_SHA256" => self.deno.sha256 = Some(value),
+                "LOBE_CHAT_VERSION" => self.lobe_chat.version = value,
+                "LOBE_CHAT_URL_PREFIX" => self.lobe_chat.url_prefix = value,
+                "LOBE_CHAT_URL" => self.lobe_chat.url = Some(value),
+                "LOBE_CHAT_SHA256" => self.lobe_chat.sha256 = Some(value),
+                "DOWNLOAD_MIRRORS" => {
+                    self.download.mirrors = value.split(',').map(|m| m.trim().to_string()).collect()
+                }
+                "HTTP_PROXY" => self.http.proxy = Some(value),
+                "HTTP_CA_BUNDLE" => self.http.ca_bundle = Some(PathBuf::from(value)),
+                "HTTP_USER_AGENT" => self.http.user_agent = Some(value),
+                "PTY_SHELL" => self.pty.shell = Some(value),
+                "LOG_LEVEL" => self.log.level = value,
+                "SERVER_PORT" => {
+                    self.server.port = value.parse().map_err(|_| ConfigError::Invalid {
+                        key: name.clone(),
+                        message: format!("`{}` is not a valid port", value),
+                    })?
+                }
+                "STARTUP_AUTOSTART" => {
+                    self.startup.autostart = value.parse().map_err(|_| ConfigError::Invalid {
+                        key: name.clone(),
+                        message: format!("`{}` is not true or false", value),
+                    })?
+                }
+                "SERVER_ENV_FILE" => self.server.env_file = Some(PathBuf::from(value)),
+                "WINDOW_HIDE_ON_CLOSE" => {
+                    self.window.hide_on_close = value.parse().map_err(|_| ConfigError::Invalid {
+                        key: name.clone(),
+                        message: format!("`{}` is not true or false", value),
+                    })?
+                }
+                "WINDOW_NAVIGATION_ALLOWLIST" => {
+                    self.window.navigation_allowlist = value
+                        .split(',')
+                        .map(|url| url.trim().to_string())
+                        .filter(|url| !url.is_empty())
+                        .collect()
+                }
+                // LOBE_CLIENT_APP_DIR 在读取配置之前处理
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(key: &str, message: &str) -> ConfigError {
+            ConfigError::Invalid {
+                key: String::from(key),
+                message: String::from(message),
+            }
+        }
+        if self.schema_version > CONFIG_SCHEMA_VERSION {
+            return Err(invalid(
+                "schema_version",
+                &format!(
+                    "{} is newer than the supported version {}",
+                    self.schema_version, CONFIG_SCHEMA_VERSION
+                ),
+            ));
+        }
+        if self.deno.version.trim().is_empty() {
+            return Err(invalid("deno.version", "must not be empty"));
+        }
+        if !is_http_url(&self.deno.url_prefix) {
+            return Err(invalid("deno.url_prefix", "must be a http(s) url"));
+        }
+        if self.deno.url.as_ref().is_some_and(|url| !is_http_url(url)) {
+            return Err(invalid("deno.url", "must be a http(s) url"));
+        }
+        if self
+            .deno
+            .sha256
+            .as_ref()
+            .is_some_and(|hash| !is_sha256(hash))
+        {
+            return Err(invalid("deno.sha256", "must be 64 hex characters"));
+        }
+        if self.lobe_chat.version.trim().is_empty() {
+            return Err(invalid("lobe_chat.version", "must not be empty"));
+        }
+        if !is_http_url(&self.lobe_chat.url_prefix) {
+            return Err(invalid("lobe_chat.url_prefix", "must be a http(s) url"));
+        }
+        if self
+            .lobe_chat
+            .url
+            .as_ref()
+            .is_some_and(|url| !is_http_url(url))
+        {
+            return Err(invalid("lobe_chat.url", "must be a http(s) url"));
+        }
+        if self
+            .lobe_chat
+            .sha256
+            .as_ref()
+            .is_some_and(|hash| !is_sha256(hash))
+        {
+            return Err(invalid("lobe_chat.sha256", "must be 64 hex characters"));
+        }
+        for mirror in self.download.mirrors.iter() {
+            if !mirror.is_empty() && !is_http_url(mirror) {
+                return Err(invalid(
+                    "download.mirrors",
+                    &format!("`{}` must be empty or a http(s) url", mirror),
+                ));
+            }
+        }
+        if self.download.retry.initial_delay_ms > self.download.retry.max_delay_ms {
+            return Err(invalid(
+                "download.retry.initial_delay_ms",
+                "must not be greater than download.retry.max_delay_ms",
+            ));
+        }
+        if let Some(proxy) = self.http.proxy.as_ref() {
+            let supported = ["http://", "https://", "socks5://", "socks5h://"];
+            if !supported.iter().any(|scheme| proxy.starts_with(scheme)) {
+                return Err(invalid(
+                    "http.proxy",
+                    "must start with http://, https://, socks5:// or socks5h://",
+                ));
+            }
+        }
+        if self.http.connect_timeout_secs == 0 {
+            return Err(invalid("http.connect_timeout_secs", "must not be 0"));
+        }
+        if self.http.read_timeout_secs == 0 {
+            return Err(invalid("http.read_timeout_secs", "must not be 0"));
+        }
+        if self.http.stall_timeout_secs == 0 {
+            return Err(invalid("http.stall_timeout_secs", "must not be 0"));
+        }
+        if self
+            .http
+            .user_agent
+            .as_ref()
+            .is_some_and(|agent| agent.trim().is_empty())
+        {
+            return Err(invalid("http.user_agent", "must not be empty"));
+        }
+        if self.server.port == 0 {
+            return Err(invalid("server.port", "must not be 0"));
+        }
+        for url in self.window.navigation_allowlist.iter() {
+            let valid =
+                is_http_url(url) && reqwest::Url::parse(url).is_ok_and(|url| url.host().is_some());
+            if !valid {
+                return Err(invalid(
+                    "window.navigation_allowlist",
+                    &format!("`{}` must be a http(s) url", url),
+                ));
+            }
+        }
+        if self.log.level.parse::<log::LevelFilter>().is_err() {
+            return Err(invalid(
+                "log.level",
+                "must be one of off, error, warn, info, debug, trace",
+            ));
+        }
+        Ok(())
+    }
+
+    /** 当前使用的加速地址,没有配置时直连 */
+    pub fn mirror(&self) -> &str {
+        self.download
+            .mirrors
+            .first()
+            .map(|m| m.as_str())
+            .unwrap_or("")
+    }
+
+    pub fn deno_zip_url(&self, target: &str) -> String {
+        if let Some(url) = self.deno.url.as_ref() {
+            return url.clone();
+        }
+        format!(
+            "{}{}/v{}/deno-{}.zip",
+            self.mirror(),
+            self.deno.url_prefix.trim_end_matches('/'),
+            self.deno.version,
+            target
+        )
+    }
+
+    /** LobeChat 打包产物名称,同时作为解压后的文件夹名 */
+    pub fn lobe_chat_bundle_name(&self) -> String {
+        format!("lobe-chat_{}", self.lobe_chat.version)
+    }
+
+    pub fn lobe_chat_zip_url(&self) -> String {
+        if let Some(url) = self.lobe_chat.url.as_ref() {
+            return url.clone();
+        }
+        format!(
+            "{}{}/{}/{}.zip",
+            self.mirror(),
+            self.lobe_chat.url_prefix.trim_end_matches('/'),
+            self.lobe_chat.version,
+            self.lobe_chat_bundle_name()
+        )
+    }
+
+    /**
+     * 运行中使用的配置,RESTART_KEYS 中的字段保留 running 的值,重启后才生效
+     * 这些字段决定了已安装的路径和状态,立即使用会与它们不一致
+     */
+    pub fn without_restart_changes(&self, running: &Self) -> Self {
+        Self {
+            server: running.server.clone(),
+            pty: running.pty.clone(),
+            deno: running.deno.clone(),
+            lobe_chat: running.lobe_chat.clone(),
+            ..self.clone()
+        }
+    }
+
+    pub fn deno_install_dir(&self) -> PathBuf {
+        match &self.deno.install_dir {
+            Some(dir) => dir.clone(),
+            None => dirs::home_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(".deno"),
+        }
+    }
+}
+
+/** 先写入临时文件再重命名,避免写入中途退出导致文件损坏 */
+pub fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/**
+ * 修改后需要重启客户端才能生效的字段
+ * 服务的地址、终端、deno 和 LobeChat 的版本、下载地址和安装路径都在启动时确定,运行中不会重新读取
+ */
+const RESTART_KEYS: &[&str] = &["server", "pty", "deno", "lobe_chat"];
+
+pub fn requires_restart(key: &str) -> bool {
+    RESTART_KEYS.iter().any(|prefix| {
+        key == *prefix
+            || key
+                .strip_prefix(prefix)
+                .map(|rest| rest.starts_with('.'))
+                .unwrap_or(false)
+    })
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch.iter() {
+                if value.is_null() {
+                    target.remove(key);
+                    continue;
+                }
+                match target.get_mut(key) {
+                    Some(existing) if existing.is_object() && value.is_object() => {
+                        merge_patch(existing, value)
+                    }
+                    _ => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+/** 以 a.b.c 的形式收集两个值之间不同的字段 */
+fn diff_keys(before: &Value, after: &Value, prefix: &str, changed: &mut Vec<String>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, value) in after.iter() {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                match before.get(key) {
+                    Some(previous) => diff_keys(previous, value, &path, changed),
+                    None => changed.push(path),
+                }
+            }
+            for key in before.keys() {
+                if !after.contains_key(key) {
+                    changed.push(if prefix.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    });
+                }
+            }
+        }
+        (before, after) => {
+            if before != after {
+                changed.push(String::from(prefix));
+            }
+        }
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/** 将字节偏移转换为从 1 开始的行号和列号 */
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = content.get(..offset).unwrap_or(content);
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map(|i| before[i + 1..].chars().count())
+        .unwrap_or_else(|| before.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{requires_restart, ClientConfig, ConfigError};
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /** 校验失败时出错的字段 */
+    fn invalid_key(config: &ClientConfig) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            r => panic!("expected invalid config, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn reports_line_and_column_of_parse_errors() {
+        let content = "schema_version = 1\n\n[server]\nport = \"abc\"\n";
+        match ClientConfig::parse(content, Path::new("config.toml")) {
+            Err(ConfigError::Parse { line, column, .. }) => assert_eq!((line, column), (4, 8)),
+            r => panic!("expected parse error, got {:?}", r),
+        }
+        match ClientConfig::parse("[server]\nhost = \"0.0.0.0\"\n", Path::new("config.toml")) {
+            Err(ConfigError::Parse { line, message, .. }) => {
+                assert_eq!(line, 2);
+                assert!(message.contains("host"), "{}", message);
+            }
+            r => panic!("expected parse error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let config =
+            ClientConfig::parse("[server]\nport = 3210\n", Path::new("config.toml")).unwrap();
+        assert_eq!(config.server.port, 3210);
+        assert_eq!(config.deno, ClientConfig::default().deno);
+    }
+
+    #[test]
+    fn applies_env_overrides() {
+        let mut config = ClientConfig::default();
+        config
+            .apply_env_overrides(env(&[
+                ("LOBE_CLIENT_SERVER_PORT", "3210"),
+                ("LOBE_CLIENT_DENO_VERSION", "2.0.0"),
+                (
+                    "LOBE_CLIENT_DOWNLOAD_MIRRORS",
+                    "https://a.example.com/, https://b.example.com/",
+                ),
+                ("LOBE_CLIENT_HTTP_PROXY", "socks5://127.0.0.1:1080"),
+                ("LOBE_CLIENT_STARTUP_AUTOSTART", "true"),
+                ("LOBE_CLIENT_APP_DIR", "/tmp/lobe"),
+                ("SERVER_PORT", "1"),
+            ]))
+            .unwrap();
+        assert_eq!(config.server.port, 3210);
+        assert_eq!(config.deno.version, "2.0.0");
+        assert_eq!(
+            config.download.mirrors,
+            vec!["https://a.example.com/", "https://b.example.com/"]
+        );
+        assert_eq!(
+            config.http.proxy.as_deref(),
+            Some("socks5://127.0.0.1:1080")
+        );
+        assert!(config.startup.autostart);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_env_overrides() {
+        for (name, value) in [
+            ("LOBE_CLIENT_SERVER_PORT", "65536"),
+            ("LOBE_CLIENT_STARTUP_AUTOSTART", "yes"),
+        ] {
+            let mut config = ClientConfig::default();
+            match config.apply_env_overrides(env(&[(name, value)])) {
+                Err(ConfigError::Invalid { key, .. }) => assert_eq!(key, name),
+                r => panic!("expected invalid {}, got {:?}", name, r),
+            }
+        }
+    }
+
+    #[test]
+    fn validate_reports_the_invalid_key() {
+        let config = ClientConfig {
+            schema_version: super::CONFIG_SCHEMA_VERSION + 1,
+            ..Default::default()
+        };
+        assert_eq!(invalid_key(&config), "schema_version");
+
+        let mut config = ClientConfig::default();
+        config.deno.version = String::from(" ");
+        assert_eq!(invalid_key(&config), "deno.version");
+
+        let mut config = ClientConfig::default();
+        config.lobe_chat.url_prefix = String::from("ftp://example.com/");
+        assert_eq!(invalid_key(&config), "lobe_chat.url_prefix");
+
+        let mut config = ClientConfig::default();
+        config.lobe_chat.sha256 = Some(String::from("abc"));
+        assert_eq!(invalid_key(&config), "lobe_chat.sha256");
+
+        let mut config = ClientConfig::default();
+        config.download.mirrors = vec![String::new(), String::from("example.com")];
+        assert_eq!(invalid_key(&config), "download.mirrors");
+
+        let mut config = ClientConfig::default();
+        config.download.retry.initial_delay_ms = config.download.retry.max_delay_ms + 1;
+        assert_eq!(invalid_key(&config), "download.retry.initial_delay_ms");
+
+        let mut config = ClientConfig::default();
+        config.http.proxy = Some(String::from("127.0.0.1:1080"));
+        assert_eq!(invalid_key(&config), "http.proxy");
+
+        let mut config = ClientConfig::default();
+        config.http.connect_timeout_secs = 0;
+        assert_eq!(invalid_key(&config), "http.connect_timeout_secs");
+
+        let mut config = ClientConfig::default();
+        config.server.port = 0;
+        assert_eq!(invalid_key(&config), "server.port");
+
+        let mut config = ClientConfig::default();
+        config.log.level = String::from("verbose");
+        assert_eq!(invalid_key(&config), "log.level");
+    }
+
+    #[test]
+    fn restart_is_required_for_server_pty_and_install_paths() {
+        let config = ClientConfig::default();
+        let mut next = config.clone();
+        next.server.port = 3210;
+        next.pty.shell = Some(String::from("/bin/zsh"));
+        next.lobe_chat.version = String::from("1.0.0");
+        next.log.level = String::from("debug");
+        next.window.hide_on_close = true;
+        let changed = config.diff(&next);
+        let restart_required = changed
+            .iter()
+            .filter(|key| requires_restart(key))
+            .cloned()
+            .collect::<Vec<String>>();
+        assert_eq!(changed.len(), 5);
+        assert_eq!(
+            restart_required,
+            vec!["lobe_chat.version", "pty.shell", "server.port"]
+        );
+        assert!(requires_restart("deno.install_dir"));
+        assert!(requires_restart("deno.sha256"));
+        assert!(!requires_restart("download.mirrors"));
+        assert!(!requires_restart("serverless"));
+    }
+
+    #[test]
+    fn keeps_restart_keys_until_restart() {
+        let running = ClientConfig::default();
+        let mut next = running.clone();
+        next.server.port = 3210;
+        next.pty.shell = Some(String::from("/bin/zsh"));
+        next.deno.version = String::from("2.0.0");
+        next.deno.install_dir = Some(PathBuf::from("/opt/deno"));
+        next.lobe_chat.version = String::from("1.0.0");
+        next.lobe_chat.sha256 = Some("0".repeat(64));
+        next.log.level = String::from("debug");
+        next.window.hide_on_close = true;
+
+        let applied = next.without_restart_changes(&running);
+        let changed = running.diff(&applied);
+        assert_eq!(changed, vec!["log.level", "window.hide_on_close"]);
+        assert!(changed.iter().all(|key| !requires_restart(key)));
+        // 重启后读取的配置包含全部修改
+        assert_eq!(running.diff(&next).len(), 8);
+    }
+}
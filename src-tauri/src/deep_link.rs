@@ -0,0 +1,218 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use reqwest::Url;
+
+use crate::error::Error;
+use crate::service::{desktop_quote, executable, write_file};
+
+/** 客户端注册的链接协议,例如 lobechat://chat?session=xxx */
+pub const SCHEME: &str = "lobechat";
+/** linux 上处理链接的 desktop 文件 */
+const DESKTOP_FILENAME: &str = "lobe-chat-client-url-handler.desktop";
+const MIME_TYPE: &str = "x-scheme-handler/lobechat";
+
+/** 是否为 lobechat:// 链接 */
+pub fn is_deep_link(value: &str) -> bool {
+    value
+        .get(..SCHEME.len() + 1)
+        .map(|prefix| prefix.eq_ignore_ascii_case(&format!("{}:", SCHEME)))
+        .unwrap_or(false)
+}
+
+/** 启动参数中的链接,系统打开链接时会把它作为参数传给客户端 */
+pub fn find(args: &[String]) -> Option<&str> {
+    args.iter()
+        .map(String::as_str)
+        .find(|arg| is_deep_link(arg))
+}
+
+/**
+ * 把链接转换为本地服务的地址
+ * lobechat://chat?session=xxx 对应 http://localhost:3210/chat?session=xxx
+ */
+pub fn resolve(link: &str, server_url: &str) -> Result<String, Error> {
+    let invalid = |message: String| Error::DeepLink {
+        link: String::from(link),
+        message,
+    };
+    let link_url = Url::parse(link).map_err(|err| invalid(err.to_string()))?;
+    if !link_url.scheme().eq_ignore_ascii_case(SCHEME) {
+        return Err(invalid(format!("scheme must be {}", SCHEME)));
+    }
+    let mut target = Url::parse(server_url)
+        .map_err(|err| invalid(format!("invalid server url {}: {}", server_url, err)))?;
+    // lobechat://chat/xxx 中的 chat 被解析为 host,和 path 一起作为服务端的路径
+    let path = format!("{}{}", link_url.host_str().unwrap_or(""), link_url.path());
+    target.set_path(&format!("/{}", path.trim_start_matches('/')));
+    target.set_query(link_url.query());
+    target.set_fragment(link_url.fragment());
+    Ok(target.to_string())
+}
+
+/**
+ * 把 lobechat:// 链接关联到当前程序,返回是否修改了系统设置
+ * linux 写入 desktop 文件后通过 xdg-mime 设为默认程序,windows 写入当前用户的注册表
+ * macOS 由打包时的 Info.plist 注册
+ */
+pub fn register() -> Result<bool, Error> {
+    let exe = executable()?;
+    if cfg!(target_os = "linux") {
+        return register_xdg(&exe);
+    }
+    if cfg!(windows) {
+        return register_windows(&exe);
+    }
+    Ok(false)
+}
+
+/** 处理链接的 desktop 文件,不显示在应用菜单中 */
+pub fn render_desktop_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\nType=Application\nName=LobeChat Client\nExec={} %u\nTerminal=false\nNoDisplay=true\nMimeType={};\n",
+        desktop_quote(&exe.display().to_string()),
+        MIME_TYPE
+    )
+}
+
+fn register_xdg(exe: &Path) -> Result<bool, Error> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| Error::NotFound(String::from("data directory")))?
+        .join("applications");
+    let path = dir.join(DESKTOP_FILENAME);
+    let entry = render_desktop_entry(exe);
+    // 每次启动都会调用,内容没有变化时不再重复设置
+    if fs::read_to_string(&path).ok().as_deref() == Some(entry.as_str()) {
+        return Ok(false);
+    }
+    write_file(&path, &entry)?;
+    if let Err(err) = run("xdg-mime", &["default", DESKTOP_FILENAME, MIME_TYPE]) {
+        let _ = fs::remove_file(&path);
+        return Err(err);
+    }
+    // 部分桌面环境通过缓存查找 MimeType,更新失败不影响 xdg-mime 的设置
+    let _ = Command::new("update-desktop-database").arg(&dir).output();
+    Ok(true)
+}
+
+fn register_windows(exe: &Path) -> Result<bool, Error> {
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    let command_key = format!(r"{}\shell\open\command", key);
+    let command = format!("\"{}\" \"%1\"", exe.display());
+    // 每次启动都会调用,已经指向当前程序时不再重复写入
+    if query_default_value(&command_key).as_deref() == Some(command.as_str()) {
+        return Ok(false);
+    }
+    run("reg", &["add", &key, "/ve", "/d", "URL:LobeChat", "/f"])?;
+    run("reg", &["add", &key, "/v", "URL Protocol", "/d", "", "/f"])?;
+    run("reg", &["add", &command_key, "/ve", "/d", &command, "/f"])?;
+    Ok(true)
+}
+
+/** 读取注册表项的默认值,不存在时返回 None */
+fn query_default_value(key: &str) -> Option<String> {
+    let output = Command::new("reg")
+        .args(["query", key, "/ve"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    parse_default_value(&String::from_utf8_lossy(&output.stdout))
+}
+
+/**
+ * 解析 reg query /ve 的输出,值所在的行形如
+ *     (Default)    REG_SZ    "C:\lobe_chat.exe" "%1"
+ * 名称随系统语言变化,按类型定位
+ */
+fn parse_default_value(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (_, rest) = line.split_once("REG_SZ")?;
+        Some(rest.trim().to_string())
+    })
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| Error::Process(format!("failed to run {}: {}", program, err)))?;
+    if !output.status.success() {
+        return Err(Error::Process(format!(
+            "`{} {}` failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, parse_default_value, resolve};
+    use crate::error::Error;
+
+    const SERVER_URL: &str = "http://localhost:3210";
+
+    #[test]
+    fn parses_registry_default_value() {
+        let output = "\r\nHKEY_CURRENT_USER\\Software\\Classes\\lobechat\\shell\\open\\command\r\n    (Default)    REG_SZ    \"C:\\Program Files\\LobeChat\\lobe_chat.exe\" \"%1\"\r\n\r\n";
+        assert_eq!(
+            parse_default_value(output).as_deref(),
+            Some("\"C:\\Program Files\\LobeChat\\lobe_chat.exe\" \"%1\"")
+        );
+        assert_eq!(
+            parse_default_value("\r\nHKEY_CURRENT_USER\\Software\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn resolves_chat_session_to_server_url() {
+        assert_eq!(
+            resolve("lobechat://chat?session=inbox", SERVER_URL).unwrap(),
+            "http://localhost:3210/chat?session=inbox"
+        );
+        assert_eq!(
+            resolve(
+                "lobechat://discover/assistant/translator#top",
+                "http://localhost:3210/"
+            )
+            .unwrap(),
+            "http://localhost:3210/discover/assistant/translator#top"
+        );
+        assert_eq!(
+            resolve("lobechat://", SERVER_URL).unwrap(),
+            "http://localhost:3210/"
+        );
+    }
+
+    #[test]
+    fn keeps_links_on_the_server_origin() {
+        assert_eq!(
+            resolve("lobechat:///../../etc/passwd", SERVER_URL).unwrap(),
+            "http://localhost:3210/etc/passwd"
+        );
+        assert_eq!(
+            resolve("lobechat://example.com//evil.com/x", SERVER_URL).unwrap(),
+            "http://localhost:3210/example.com//evil.com/x"
+        );
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        let err = resolve("https://example.com/chat", SERVER_URL).unwrap_err();
+        assert!(matches!(err, Error::DeepLink { .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn finds_link_in_launch_args() {
+        let args = vec![
+            String::from("--hidden"),
+            String::from("LobeChat://chat?session=inbox"),
+        ];
+        assert_eq!(find(&args), Some("LobeChat://chat?session=inbox"));
+        assert_eq!(find(&[String::from("lobechat")]), None);
+    }
+}
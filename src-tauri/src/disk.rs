@@ -0,0 +1,31 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+
+/** 下载前无法得知解压后的大小,按压缩包大小的倍数估算 */
+pub const ESTIMATED_EXTRACT_RATIO: u64 = 3;
+/** 额外预留的空间,避免把磁盘写满 */
+const RESERVED_SPACE: u64 = 64 * 1024 * 1024;
+
+/** 路径所在文件系统的可用空间,路径不存在时使用最近的已存在的上级目录 */
+pub fn available_space(path: &Path) -> Result<u64, Error> {
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or_else(|| Path::new("."));
+    Ok(fs2::available_space(existing)?)
+}
+
+/** 检查可用空间是否足够写入 required 字节,不够时返回 InsufficientDiskSpace */
+pub fn ensure_space(path: &Path, required: u64) -> Result<(), Error> {
+    let available = available_space(path)?;
+    let required = required.saturating_add(RESERVED_SPACE);
+    if available < required {
+        return Err(Error::InsufficientDiskSpace {
+            path: PathBuf::from(path),
+            required,
+            available,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::config::RetryConfig;
+
+/** 需要下载的文件 */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Artifact {
+    /** deno 压缩包 */
+    Deno,
+    /** LobeChat 打包产物 */
+    LobeChat,
+}
+
+impl Artifact {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Artifact::Deno => "deno",
+            Artifact::LobeChat => "lobe_chat",
+        }
+    }
+}
+
+/**
+ * 下载的取消标记
+ * 下载循环中等待 cancelled(),调用 cancel 后下载会立即停止
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelInner>,
+}
+
+#[derive(Debug, Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    /** 取消后是否保留已下载的部分,用于下次继续下载 */
+    keep_partial: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self, keep_partial: bool) {
+        self.inner
+            .keep_partial
+            .store(keep_partial, Ordering::SeqCst);
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn keep_partial(&self) -> bool {
+        self.inner.keep_partial.load(Ordering::SeqCst)
+    }
+
+    /** 等待取消 */
+    pub async fn cancelled(&self) {
+        loop {
+            // 先创建 notified 再检查标记,避免错过 cancel 时的通知
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/**
+ * 下载管理
+ * 同一个文件同时只会有一个下载任务,重复发起时调用方直接使用已有任务的进度
+ */
+#[derive(Debug, Default)]
+pub struct DownloadManager {
+    tokens: Mutex<HashMap<Artifact, CancelToken>>,
+}
+
+impl DownloadManager {
+    /** 登记下载任务并返回取消标记,已有同一文件的任务时返回 None */
+    pub fn claim(&self, artifact: Artifact) -> Option<CancelToken> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.contains_key(&artifact) {
+            return None;
+        }
+        let token = CancelToken::default();
+        tokens.insert(artifact, token.clone());
+        Some(token)
+    }
+
+    /** 任务结束后释放,之后可以重新下载 */
+    pub fn finish(&self, artifact: Artifact) {
+        self.tokens.lock().unwrap().remove(&artifact);
+    }
+
+    pub fn is_running(&self, artifact: Artifact) -> bool {
+        self.tokens.lock().unwrap().contains_key(&artifact)
+    }
+
+    /** 取消下载,没有正在进行的下载时返回 false */
+    pub fn cancel(&self, artifact: Artifact, keep_partial: bool) -> bool {
+        match self.tokens.lock().unwrap().get(&artifact) {
+            Some(token) => {
+                token.cancel(keep_partial);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /** 取消所有下载,退出客户端时使用,返回被取消的下载 */
+    pub fn cancel_all(&self, keep_partial: bool) -> Vec<Artifact> {
+        let tokens = self.tokens.lock().unwrap();
+        for token in tokens.values() {
+            token.cancel(keep_partial);
+        }
+        tokens.keys().copied().collect()
+    }
+}
+
+/** 下载中的临时文件,下载完成后重命名为目标文件 */
+pub fn partial_path(filepath: &Path) -> PathBuf {
+    let mut name = filepath
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    name.push(".part");
+    filepath.with_file_name(name)
+}
+
+/** 两次进度通知的最小间隔,避免每收到一段数据就通知一次 */
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/** 计算速度时新采样所占的比重 */
+const SPEED_SMOOTHING: f64 = 0.3;
+
+/**
+ * 下载进度
+ * 服务端没有返回 Content-Length 时 total、percent、eta_secs 为 None,前端展示为不确定的进度
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DownloadProgress {
+    /** 已下载的字节数,包含之前下载的部分 */
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    pub percent: Option<f64>,
+    /** 当前速度,字节/秒 */
+    pub speed: f64,
+    /** 预计剩余秒数 */
+    pub eta_secs: Option<u64>,
+}
+
+/** 统计下载进度,按 PROGRESS_INTERVAL 限制通知频率 */
+#[derive(Debug)]
+pub struct ProgressTracker {
+    downloaded: u64,
+    total: Option<u64>,
+    speed: f64,
+    sample_at: Instant,
+    sample_bytes: u64,
+    emitted_at: Instant,
+}
+
+impl ProgressTracker {
+    /** 创建后调用方应先通知一次初始进度 */
+    pub fn new(downloaded: u64, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        ProgressTracker {
+            downloaded,
+            total,
+            speed: 0.0,
+            sample_at: now,
+            sample_bytes: 0,
+            emitted_at: now,
+        }
+    }
+
+    /** 记录新收到的数据,距离上次通知超过间隔时返回新的进度 */
+    pub fn advance(&mut self, len: u64) -> Option<DownloadProgress> {
+        self.downloaded += len;
+        self.sample_bytes += len;
+        let now = Instant::now();
+        if now.duration_since(self.emitted_at) < PROGRESS_INTERVAL {
+            return None;
+        }
+        self.sample(now);
+        self.emitted_at = now;
+        Some(self.progress())
+    }
+
+    /** 下载完成时的进度,不受通知间隔限制,速度沿用上次的值 */
+    pub fn finish(&mut self) -> DownloadProgress {
+        if self.total.is_none() {
+            self.total = Some(self.downloaded);
+        }
+        self.progress()
+    }
+
+    fn sample(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.sample_at).as_secs_f64();
+        let current = self.sample_bytes as f64 / elapsed;
+        self.speed = if self.speed == 0.0 {
+            current
+        } else {
+            SPEED_SMOOTHING * current + (1.0 - SPEED_SMOOTHING) * self.speed
+        };
+        self.sample_at = now;
+        self.sample_bytes = 0;
+    }
+
+    pub fn progress(&self) -> DownloadProgress {
+        let percent = self
+            .total
+            .filter(|total| *total > 0)
+            .map(|total| (self.downloaded as f64 / total as f64 * 100.0).min(100.0));
+        let eta_secs = match self.total {
+            Some(total) if self.speed > 0.0 => {
+                Some((total.saturating_sub(self.downloaded) as f64 / self.speed).ceil() as u64)
+            }
+            _ => None,
+        };
+        DownloadProgress {
+            downloaded: self.downloaded,
+            total: self.total,
+            percent,
+            speed: self.speed,
+            eta_secs,
+        }
+    }
+}
+
+/**
+ * 下载停滞检测
+ * 每 timeout 统计一次收到的数据,平均速度低于 min_speed 时视为停滞
+ */
+#[derive(Debug)]
+pub struct StallDetector {
+    timeout: Duration,
+    min_speed: u64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl StallDetector {
+    pub fn new(timeout: Duration, min_speed: u64) -> Self {
+        StallDetector {
+            timeout,
+            min_speed,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /** 记录收到的数据,已停滞时返回 true */
+    pub fn record(&mut self, len: u64) -> bool {
+        self.window_bytes += len;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < self.timeout {
+            return false;
+        }
+        let stalled = (self.window_bytes as f64 / elapsed.as_secs_f64()) < self.min_speed as f64;
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        stalled
+    }
+}
+
+/** 服务端通过 Retry-After 要求等待更久时不再重试,直接返回错误 */
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/**
+ * 重试等待时间,指数退避并加入随机抖动
+ * 服务端指定了 Retry-After 时以其为准,超过 MAX_RETRY_AFTER 时返回 None 表示放弃重试
+ */
+pub fn retry_delay(
+    config: &RetryConfig,
+    attempt: u32,
+    retry_after: Option<u64>,
+) -> Option<Duration> {
+    if let Some(secs) = retry_after {
+        let delay = Duration::from_secs(secs);
+        return (delay <= MAX_RETRY_AFTER).then_some(delay);
+    }
+    let exp = attempt.saturating_sub(1).min(16);
+    let ceiling = config
+        .initial_delay_ms
+        .saturating_mul(1 << exp)
+        .min(config.max_delay_ms);
+    // 只用于错开多个客户端的重试时间,不需要真正的随机数
+    let random = RandomState::new().build_hasher().finish();
+    Some(Duration::from_millis(random % (ceiling + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{retry_delay, Artifact, DownloadManager, MAX_RETRY_AFTER};
+    use crate::config::RetryConfig;
+
+    #[test]
+    fn claims_each_artifact_once() {
+        let downloads = DownloadManager::default();
+        assert!(downloads.claim(Artifact::Deno).is_some());
+        assert!(downloads.claim(Artifact::Deno).is_none());
+        assert!(downloads.is_running(Artifact::Deno));
+        // 不同的文件可以同时下载
+        assert!(downloads.claim(Artifact::LobeChat).is_some());
+    }
+
+    #[test]
+    fn finish_releases_the_slot() {
+        let downloads = DownloadManager::default();
+        let token = downloads.claim(Artifact::LobeChat).unwrap();
+        downloads.finish(Artifact::LobeChat);
+        assert!(!downloads.is_running(Artifact::LobeChat));
+        assert!(!downloads.cancel(Artifact::LobeChat, false));
+        assert!(!token.is_cancelled());
+        assert!(downloads.claim(Artifact::LobeChat).is_some());
+    }
+
+    #[test]
+    fn cancel_reaches_the_claimed_token() {
+        let downloads = DownloadManager::default();
+        let deno = downloads.claim(Artifact::Deno).unwrap();
+        let lobe_chat = downloads.claim(Artifact::LobeChat).unwrap();
+        assert!(downloads.cancel(Artifact::Deno, true));
+        assert!(deno.is_cancelled());
+        assert!(deno.keep_partial());
+        assert!(!lobe_chat.is_cancelled());
+
+        let mut cancelled = downloads.cancel_all(false);
+        cancelled.sort_by_key(|artifact| artifact.name());
+        assert_eq!(cancelled, vec![Artifact::Deno, Artifact::LobeChat]);
+        assert!(lobe_chat.is_cancelled());
+        assert!(!lobe_chat.keep_partial());
+    }
+
+    #[test]
+    fn honors_retry_after_beyond_max_delay() {
+        let retry = RetryConfig {
+            max_retries: 3,
+            initial_delay_ms: 1,
+            max_delay_ms: 10,
+        };
+        assert_eq!(
+            retry_delay(&retry, 1, Some(2)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            retry_delay(&retry, 1, Some(MAX_RETRY_AFTER.as_secs() + 1)),
+            None
+        );
+        assert!(retry_delay(&retry, 1, None).unwrap().as_millis() <= 1);
+    }
+}
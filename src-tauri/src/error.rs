@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+
+use serde::ser::SerializeStruct;
+use serde_json::{json, Value};
+
+use crate::config::ConfigError;
+
+/**
+ * 应用中所有可能出现的错误
+ * 返回给前端时序列化为 { code, kind, message, context },code 是稳定的,可以用来判断错误类型
+ */
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("request {url} failed: {source}")]
+    Http {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("request {url} responded with status {status}")]
+    Status {
+        url: String,
+        status: u16,
+        /** 响应头 Retry-After 中的秒数 */
+        retry_after: Option<u64>,
+    },
+    #[error("download {url} stalled, less than {min_speed} bytes/s in the last {secs}s")]
+    Stalled {
+        url: String,
+        secs: u64,
+        min_speed: u64,
+    },
+    #[error("checksum mismatch for {path}, expected {expected} but got {actual}")]
+    Checksum {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("failed to extract {path}: {message}")]
+    Extract { path: PathBuf, message: String },
+    #[error("invalid installation at {path}: {message}")]
+    Install { path: PathBuf, message: String },
+    #[error(
+        "not enough disk space at {path}, {required} bytes required but only {available} available"
+    )]
+    InsufficientDiskSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    #[error("pty error: {0}")]
+    Pty(String),
+    #[error("process error: {0}")]
+    Process(String),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("invalid link {link}: {message}")]
+    DeepLink { link: String, message: String },
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0} was cancelled")]
+    Cancelled(String),
+}
+
+impl Error {
+    /** 稳定的错误码,前端和日志依赖它,不要修改已有的值 */
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io_error",
+            Error::Http { .. } => "http_error",
+            Error::Status { .. } => "http_status",
+            Error::Stalled { .. } => "download_stalled",
+            Error::Checksum { .. } => "checksum_mismatch",
+            Error::Extract { .. } => "extract_failed",
+            Error::Install { .. } => "install_invalid",
+            Error::InsufficientDiskSpace { .. } => "insufficient_disk_space",
+            Error::Pty(_) => "pty_error",
+            Error::Process(_) => "process_error",
+            Error::Config(_) => "config_error",
+            Error::DeepLink { .. } => "invalid_deep_link",
+            Error::NotFound(_) => "not_found",
+            Error::Cancelled(_) => "cancelled",
+        }
+    }
+
+    /** 错误分类,用于前端决定展示方式 */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Io(_) | Error::InsufficientDiskSpace { .. } => "filesystem",
+            Error::Http { .. } | Error::Status { .. } | Error::Stalled { .. } => "network",
+            Error::Checksum { .. } | Error::Extract { .. } | Error::Install { .. } => "artifact",
+            Error::Pty(_) | Error::Process(_) => "process",
+            Error::Config(_) => "config",
+            Error::DeepLink { .. } => "deep_link",
+            Error::NotFound(_) => "not_found",
+            Error::Cancelled(_) => "cancelled",
+        }
+    }
+
+    /**
+     * 是否为临时性的错误,重试可能成功
+     * 包括连接失败、超时、传输中断、下载停滞以及 408、429、5xx 响应
+     */
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // 读取响应时连接断开,reqwest 归为 decode 错误
+            Error::Http { source, .. } => {
+                source.is_connect()
+                    || source.is_timeout()
+                    || source.is_request()
+                    || source.is_body()
+                    || source.is_decode()
+            }
+            Error::Status { status, .. } => *status == 408 || *status == 429 || *status >= 500,
+            Error::Stalled { .. } => true,
+            _ => false,
+        }
+    }
+
+    /** 服务端要求的重试等待时间 */
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            Error::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /** 错误相关的额外信息 */
+    pub fn context(&self) -> Value {
+        match self {
+            Error::Io(err) => json!({ "io_kind": format!("{:?}", err.kind()) }),
+            Error::Http { url, source } => json!({
+                "url": url,
+                "timeout": source.is_timeout(),
+                "connect": source.is_connect(),
+            }),
+            Error::Status {
+                url,
+                status,
+                retry_after,
+            } => json!({ "url": url, "status": status, "retry_after": retry_after }),
+            Error::Stalled {
+                url,
+                secs,
+                min_speed,
+            } => json!({ "url": url, "secs": secs, "min_speed": min_speed }),
+            Error::Checksum {
+                path,
+                expected,
+                actual,
+            } => json!({ "path": path, "expected": expected, "actual": actual }),
+            Error::Extract { path, .. } | Error::Install { path, .. } => json!({ "path": path }),
+            Error::InsufficientDiskSpace {
+                path,
+                required,
+                available,
+            } => json!({ "path": path, "required": required, "available": available }),
+            Error::Config(ConfigError::Io { path, .. }) => json!({ "path": path }),
+            Error::Config(ConfigError::Parse {
+                path, line, column, ..
+            }) => json!({ "path": path, "line": line, "column": column }),
+            Error::Config(ConfigError::Invalid { key, .. }) => json!({ "key": key }),
+            Error::DeepLink { link, .. } => json!({ "link": link }),
+            Error::NotFound(target) | Error::Cancelled(target) => json!({ "target": target }),
+            _ => Value::Null,
+        }
+    }
+}
+
+// 返回给前端时需要 serde::Serialize
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("context", &self.context())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::Error;
+
+    /** 返回 Content-Length 后只发送一半的数据就断开连接 */
+    fn serve_truncated_body() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/lobe-chat.zip", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0; 1024];
+            while !head.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => head.extend_from_slice(&buf[..n]),
+                }
+            }
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\n");
+            let _ = stream.write_all(&[b'a'; 512]);
+        });
+        url
+    }
+
+    #[test]
+    fn connection_dropped_mid_body_is_transient() {
+        let url = serve_truncated_body();
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        let source = client
+            .get(&url)
+            .send()
+            .and_then(|response| response.bytes())
+            .unwrap_err();
+        let err = Error::Http { url, source };
+        assert!(err.is_transient(), "{:?}", err);
+    }
+}
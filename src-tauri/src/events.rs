@@ -0,0 +1,143 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::download::DownloadProgress;
+use crate::error::Error;
+use crate::extract::ExtractProgress;
+use crate::setup_phase::SetupPhase;
+
+/** 安装和终端产生的通知,窗口中转为前端事件,命令行中输出到控制台 */
+#[derive(Debug)]
+pub enum AppEvent {
+    Phase(SetupPhase),
+    /** 下载失败后准备重试 */
+    Retrying {
+        url: String,
+        attempt: u32,
+        max_retries: u32,
+        delay: Duration,
+        reason: Error,
+    },
+    ExtractProgress {
+        file: PathBuf,
+        progress: ExtractProgress,
+    },
+    /** pty 的输出 */
+    TermData(Vec<u8>),
+    /** 读取 pty 失败,之后不会再有输出 */
+    PtyError(Error),
+}
+
+/**
+ * 接收通知
+ * 核心逻辑只依赖这个 trait,tauri、命令行和测试各自实现
+ */
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: AppEvent);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(AppEvent) + Send + Sync,
+{
+    fn emit(&self, event: AppEvent) {
+        self(event)
+    }
+}
+
+/** 把通知保存在内存中,用于测试 */
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    events: Mutex<Vec<AppEvent>>,
+}
+
+impl MemorySink {
+    /** 取出目前收到的所有通知 */
+    pub fn take(&self) -> Vec<AppEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl EventSink for MemorySink {
+    fn emit(&self, event: AppEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+/** 把下载、解压的进度输出到控制台,用于命令行 */
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl EventSink for StdoutSink {
+    fn emit(&self, event: AppEvent) {
+        match event {
+            AppEvent::Phase(SetupPhase::DownloadingRuntime { progress, .. }) => {
+                println!("downloading deno {}", format_progress(&progress))
+            }
+            AppEvent::Phase(SetupPhase::DownloadingBundle { progress, .. }) => {
+                println!("downloading LobeChat {}", format_progress(&progress))
+            }
+            AppEvent::Phase(SetupPhase::Extracting { file }) => {
+                println!("extracting {}", file.display())
+            }
+            AppEvent::Phase(_) => {}
+            AppEvent::Retrying {
+                attempt,
+                max_retries,
+                delay,
+                reason,
+                ..
+            } => println!(
+                "{}, retry {}/{} in {:.1}s",
+                reason,
+                attempt,
+                max_retries,
+                delay.as_secs_f64()
+            ),
+            AppEvent::ExtractProgress { progress, .. } => match progress.entries_total {
+                Some(total) => println!(
+                    "extracted {}/{} files, {}",
+                    progress.entries_done,
+                    total,
+                    format_bytes(progress.bytes_done as f64)
+                ),
+                None => println!(
+                    "extracted {} files, {}",
+                    progress.entries_done,
+                    format_bytes(progress.bytes_done as f64)
+                ),
+            },
+            AppEvent::TermData(data) => print!("{}", String::from_utf8_lossy(&data)),
+            AppEvent::PtyError(err) => eprintln!("error: {}", err),
+        }
+    }
+}
+
+fn format_progress(progress: &DownloadProgress) -> String {
+    let mut text = match (progress.percent, progress.total) {
+        (Some(percent), Some(total)) => format!(
+            "{:.1}% {}/{}",
+            percent,
+            format_bytes(progress.downloaded as f64),
+            format_bytes(total as f64)
+        ),
+        _ => format_bytes(progress.downloaded as f64),
+    };
+    text.push_str(&format!(" {}/s", format_bytes(progress.speed)));
+    if let Some(eta) = progress.eta_secs {
+        text.push_str(&format!(" {}s left", eta));
+    }
+    text
+}
+
+fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, units[unit])
+}
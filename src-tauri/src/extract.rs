@@ -0,0 +1,330 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::disk;
+use crate::download::{CancelToken, PROGRESS_INTERVAL};
+use crate::error::Error;
+
+/** 压缩包格式,根据文件开头的魔数判断,不依赖文件名 */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+    TarXz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /** 读取文件开头判断格式 */
+    pub fn detect(path: &Path) -> Result<Self, Error> {
+        let mut magic = [0u8; 6];
+        let mut file = fs::File::open(path)?;
+        let mut len = 0;
+        while len < magic.len() {
+            let n = file.read(&mut magic[len..])?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        Self::from_magic(&magic[..len]).ok_or_else(|| Error::Extract {
+            path: path.to_path_buf(),
+            message: String::from("unsupported archive format"),
+        })
+    }
+
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            return Some(ArchiveKind::Zip);
+        }
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            return Some(ArchiveKind::TarGz);
+        }
+        if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            return Some(ArchiveKind::TarXz);
+        }
+        if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Some(ArchiveKind::TarZst);
+        }
+        None
+    }
+
+    /** 根据下载地址推测的后缀,只用于命名下载的文件,解压时仍以魔数为准 */
+    pub fn suffix_of(url: &str) -> &'static str {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        [
+            ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.zst", ".tzst", ".zip",
+        ]
+        .into_iter()
+        .find(|suffix| path.ends_with(suffix))
+        .unwrap_or(".zip")
+    }
+
+    fn extractor(&self) -> Box<dyn Extractor> {
+        match self {
+            ArchiveKind::Zip => Box::new(ZipExtractor),
+            kind => Box::new(TarExtractor { kind: *kind }),
+        }
+    }
+}
+
+/** 解压进度,无法预先知道文件数量时 entries_total 为 None */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExtractProgress {
+    pub entries_done: u64,
+    pub entries_total: Option<u64>,
+    /** 已写入的解压后字节数 */
+    pub bytes_done: u64,
+}
+
+/**
+ * 解压器
+ * 按 PROGRESS_INTERVAL 调用 on_progress,每写入一段数据检查一次是否取消
+ */
+trait Extractor {
+    fn extract(
+        &self,
+        archive: &Path,
+        output_folder: &Path,
+        token: &CancelToken,
+        on_progress: &mut dyn FnMut(&ExtractProgress),
+    ) -> Result<(), Error>;
+}
+
+/**
+ * 解压压缩包到指定目录,返回压缩包的格式
+ * 支持 zip、tar.gz、tar.xz、tar.zst
+ */
+pub fn extract(
+    archive: &Path,
+    output_folder: &Path,
+    token: &CancelToken,
+    mut on_progress: impl FnMut(&ExtractProgress),
+) -> Result<ArchiveKind, Error> {
+    let kind = ArchiveKind::detect(archive)?;
+    kind.extractor()
+        .extract(archive, output_folder, token, &mut on_progress)?;
+    Ok(kind)
+}
+
+/** zip 的文件目录在压缩包末尾,只能下载完成后再解压 */
+struct ZipExtractor;
+
+impl Extractor for ZipExtractor {
+    fn extract(
+        &self,
+        zip_path: &Path,
+        output_folder: &Path,
+        token: &CancelToken,
+        on_progress: &mut dyn FnMut(&ExtractProgress),
+    ) -> Result<(), Error> {
+        let extract_error = |message: String| Error::Extract {
+            path: zip_path.to_path_buf(),
+            message,
+        };
+        let zip_file = fs::File::open(zip_path)?;
+        let mut archive = ZipArchive::new(zip_file).map_err(|e| extract_error(e.to_string()))?;
+        // 解压前根据 zip 目录中记录的大小检查磁盘空间,避免解压到一半失败
+        let mut uncompressed_size: u64 = 0;
+        for i in 0..archive.len() {
+            let file = archive
+                .by_index_raw(i)
+                .map_err(|e| extract_error(e.to_string()))?;
+            uncompressed_size = uncompressed_size.saturating_add(file.size());
+        }
+        disk::ensure_space(output_folder, uncompressed_size)?;
+
+        let mut progress = ExtractProgress {
+            entries_total: Some(archive.len() as u64),
+            ..Default::default()
+        };
+        let mut reported_at = Instant::now();
+        on_progress(&progress);
+        for i in 0..archive.len() {
+            let mut file = archive
+                .by_index(i)
+                .map_err(|e| extract_error(e.to_string()))?;
+            let out_path = match file.enclosed_name() {
+                Some(name) => output_folder.join(name),
+                None => {
+                    return Err(extract_error(format!(
+                        "entry `{}` escapes the output directory",
+                        file.name()
+                    )))
+                }
+            };
+
+            // 确保输出目录存在
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // 解压文件
+            if file.is_dir() {
+                fs::create_dir_all(&out_path)?;
+            } else {
+                let mut out_file = fs::File::create(&out_path)?;
+                progress.bytes_done += copy(&mut file, &mut out_file, token, zip_path)?;
+            }
+            progress.entries_done += 1;
+            if reported_at.elapsed() >= PROGRESS_INTERVAL {
+                reported_at = Instant::now();
+                on_progress(&progress);
+            }
+        }
+        on_progress(&progress);
+        Ok(())
+    }
+}
+
+/** tar 包按顺序读取,不需要先读完整个文件 */
+struct TarExtractor {
+    kind: ArchiveKind,
+}
+
+impl Extractor for TarExtractor {
+    fn extract(
+        &self,
+        path: &Path,
+        output_folder: &Path,
+        token: &CancelToken,
+        on_progress: &mut dyn FnMut(&ExtractProgress),
+    ) -> Result<(), Error> {
+        let extract_error = |message: String| Error::Extract {
+            path: path.to_path_buf(),
+            message,
+        };
+        // tar 包中没有记录解压后的总大小,按压缩包大小估算
+        let archive_size = fs::metadata(path)?.len();
+        disk::ensure_space(
+            output_folder,
+            archive_size.saturating_mul(disk::ESTIMATED_EXTRACT_RATIO),
+        )?;
+        let file = io::BufReader::new(fs::File::open(path)?);
+        let decoder: Box<dyn Read> = match self.kind {
+            ArchiveKind::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+            ArchiveKind::TarXz => Box::new(xz2::read::XzDecoder::new(file)),
+            ArchiveKind::TarZst => Box::new(
+                zstd::stream::read::Decoder::new(file).map_err(|e| extract_error(e.to_string()))?,
+            ),
+            ArchiveKind::Zip => unreachable!("zip is handled by ZipExtractor"),
+        };
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_preserve_permissions(true);
+        fs::create_dir_all(output_folder)?;
+
+        let mut progress = ExtractProgress::default();
+        let mut reported_at = Instant::now();
+        on_progress(&progress);
+        let entries = archive
+            .entries()
+            .map_err(|e| extract_error(e.to_string()))?;
+        for entry in entries {
+            if token.is_cancelled() {
+                return Err(Error::Cancelled(path.display().to_string()));
+            }
+            let mut entry = entry.map_err(|e| extract_error(e.to_string()))?;
+            let size = entry.size();
+            // unpack_in 会拒绝解压到目标目录之外的文件
+            let unpacked = entry
+                .unpack_in(output_folder)
+                .map_err(|e| extract_error(e.to_string()))?;
+            if !unpacked {
+                return Err(extract_error(format!(
+                    "entry `{}` escapes the output directory",
+                    entry
+                        .path()
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default()
+                )));
+            }
+            progress.entries_done += 1;
+            progress.bytes_done += size;
+            if reported_at.elapsed() >= PROGRESS_INTERVAL {
+                reported_at = Instant::now();
+                on_progress(&progress);
+            }
+        }
+        on_progress(&progress);
+        Ok(())
+    }
+}
+
+/** 复制解压后的数据,每写入一段检查一次是否取消 */
+fn copy(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    token: &CancelToken,
+    archive: &Path,
+) -> Result<u64, Error> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut written: u64 = 0;
+    loop {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled(archive.display().to_string()));
+        }
+        let n = reader.read(&mut buf).map_err(|e| Error::Extract {
+            path: archive.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        if n == 0 {
+            return Ok(written);
+        }
+        writer.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveKind;
+
+    #[test]
+    fn detects_kind_from_magic() {
+        assert_eq!(
+            ArchiveKind::from_magic(b"PK\x03\x04\x14\x00"),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(
+            ArchiveKind::from_magic(b"PK\x05\x06"),
+            Some(ArchiveKind::Zip)
+        );
+        assert_eq!(
+            ArchiveKind::from_magic(&[0x1f, 0x8b, 0x08, 0x00]),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(
+            ArchiveKind::from_magic(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Some(ArchiveKind::TarXz)
+        );
+        assert_eq!(
+            ArchiveKind::from_magic(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Some(ArchiveKind::TarZst)
+        );
+        // 镜像返回的错误页面
+        assert_eq!(ArchiveKind::from_magic(b"<html>"), None);
+        assert_eq!(ArchiveKind::from_magic(&[0xfd, b'7', b'z']), None);
+        assert_eq!(ArchiveKind::from_magic(&[]), None);
+    }
+
+    #[test]
+    fn guesses_suffix_from_url() {
+        assert_eq!(
+            ArchiveKind::suffix_of("https://example.com/deno.tar.zst?token=1"),
+            ".tar.zst"
+        );
+        assert_eq!(
+            ArchiveKind::suffix_of("https://example.com/deno.tgz"),
+            ".tgz"
+        );
+        assert_eq!(
+            ArchiveKind::suffix_of("https://example.com/download"),
+            ".zip"
+        );
+    }
+}
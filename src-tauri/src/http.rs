@@ -0,0 +1,49 @@
+use std::fs;
+use std::time::Duration;
+
+use reqwest::{Certificate, Client, Proxy};
+
+use crate::config::{ConfigError, HttpConfig};
+use crate::error::Error;
+
+/**
+ * 根据配置创建 HTTP 客户端
+ * 所有下载共用同一个客户端,修改 http 相关设置后重新创建
+ */
+pub fn build_client(config: &HttpConfig) -> Result<Client, Error> {
+    let invalid = |key: &str, message: String| ConfigError::Invalid {
+        key: String::from(key),
+        message,
+    };
+    let user_agent = config
+        .user_agent
+        .clone()
+        .unwrap_or_else(|| format!("LobeChatClient/{}", env!("CARGO_PKG_VERSION")));
+    let mut builder = Client::builder()
+        .user_agent(user_agent)
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+        .read_timeout(Duration::from_secs(config.read_timeout_secs));
+    // 没有配置代理时 reqwest 会读取 HTTP_PROXY 等环境变量
+    if let Some(proxy) = config.proxy.as_ref() {
+        let proxy = Proxy::all(proxy).map_err(|err| invalid("http.proxy", err.to_string()))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(path) = config.ca_bundle.as_ref() {
+        let pem = fs::read(path).map_err(|source| ConfigError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .map_err(|err| invalid("http.ca_bundle", err.to_string()))?;
+        if certs.is_empty() {
+            return Err(invalid("http.ca_bundle", String::from("no certificate found")).into());
+        }
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    let client = builder
+        .build()
+        .map_err(|err| invalid("http", err.to_string()))?;
+    Ok(client)
+}
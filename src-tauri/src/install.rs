@@ -0,0 +1,244 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::write_atomic;
+use crate::download::Artifact;
+use crate::error::Error;
+use crate::extract::ArchiveKind;
+
+/** 安装记录所在的文件夹,位于应用目录下 */
+const MANIFEST_DIR: &str = "installs";
+/** LobeChat 服务的入口文件,用于判断打包产物是否完整 */
+pub const LOBE_CHAT_ENTRY: &str = "server.cjs";
+
+/**
+ * 安装记录
+ * 解压并校验通过后才写入,没有记录的安装视为不完整
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallManifest {
+    pub artifact: Artifact,
+    pub version: String,
+    /** 压缩包的格式,沿用已有的 deno 时为 None */
+    #[serde(default)]
+    pub archive: Option<ArchiveKind>,
+    /** 压缩包的 sha256,沿用已有的 deno 时为 None */
+    pub sha256: Option<String>,
+    /** 安装时间,unix 时间戳,单位秒 */
+    pub installed_at: u64,
+}
+
+impl InstallManifest {
+    pub fn new(
+        artifact: Artifact,
+        version: &str,
+        archive: Option<ArchiveKind>,
+        sha256: Option<String>,
+    ) -> Self {
+        InstallManifest {
+            artifact,
+            version: String::from(version),
+            archive,
+            sha256,
+            installed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn path(app_dir: &Path, artifact: Artifact) -> PathBuf {
+        app_dir
+            .join(MANIFEST_DIR)
+            .join(format!("{}.json", artifact.name()))
+    }
+
+    /** 读取安装记录,不存在或损坏时返回 None */
+    pub fn read(app_dir: &Path, artifact: Artifact) -> Option<Self> {
+        let content = fs::read(Self::path(app_dir, artifact)).ok()?;
+        serde_json::from_slice::<Self>(&content).ok()
+    }
+
+    pub fn save(&self, app_dir: &Path) -> Result<(), Error> {
+        let path = Self::path(app_dir, self.artifact);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = serde_json::to_vec_pretty(self).map_err(|err| Error::Install {
+            path: path.clone(),
+            message: err.to_string(),
+        })?;
+        write_atomic(&path, &content)?;
+        Ok(())
+    }
+
+    pub fn remove(app_dir: &Path, artifact: Artifact) {
+        let _ = fs::remove_file(Self::path(app_dir, artifact));
+    }
+}
+
+/** 解压时使用的临时目录,与安装位置在同一个文件夹下,保证可以直接重命名 */
+pub fn staging_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.staging", name))
+}
+
+/** 在目录及其下一级目录中查找文件,部分压缩包会把文件放在一个文件夹里 */
+pub fn find_file(dir: &Path, filename: &OsStr) -> Option<PathBuf> {
+    let path = dir.join(filename);
+    if path.is_file() {
+        return Some(path);
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path().join(filename))
+        .find(|path| path.is_file())
+}
+
+/** 计算文件的 sha256,返回小写的十六进制字符串 */
+pub fn sha256_file(path: &Path) -> Result<String, Error> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/** 校验 LobeChat 打包产物,入口文件存在才认为完整 */
+pub fn validate_lobe_chat(dir: &Path) -> Result<(), Error> {
+    if !dir.join(LOBE_CHAT_ENTRY).is_file() {
+        return Err(Error::Install {
+            path: dir.to_path_buf(),
+            message: format!("{} not found", LOBE_CHAT_ENTRY),
+        });
+    }
+    Ok(())
+}
+
+/** 执行 deno --version 校验 deno 可以运行,返回版本号 */
+pub fn validate_deno(bin: &Path) -> Result<String, Error> {
+    let invalid = |message: String| Error::Install {
+        path: bin.to_path_buf(),
+        message,
+    };
+    let output = Command::new(bin)
+        .arg("--version")
+        .output()
+        .map_err(|err| invalid(err.to_string()))?;
+    if !output.status.success() {
+        return Err(invalid(format!(
+            "`deno --version` exited with {}",
+            output.status
+        )));
+    }
+    // 输出形如 deno 2.1.4 (stable, release, x86_64-apple-darwin)
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .split_whitespace()
+        .nth(1)
+        .map(String::from)
+        .ok_or_else(|| {
+            invalid(format!(
+                "unexpected `deno --version` output: {}",
+                stdout.trim()
+            ))
+        })
+}
+
+/** 用临时目录替换安装目录 */
+pub fn commit_dir(staging: &Path, target: &Path) -> Result<(), Error> {
+    if target.exists() {
+        fs::remove_dir_all(target)?;
+    }
+    fs::rename(staging, target)?;
+    Ok(())
+}
+
+/**
+ * 启动时检查 LobeChat 是否完整安装
+ * 入口文件缺失时清理安装目录,之后重新解压
+ * 没有安装记录或记录的版本不一致时,安装目录名中已包含版本号,校验通过就补上记录
+ */
+pub fn check_lobe_chat(app_dir: &Path, dir: &Path, version: &str) -> bool {
+    let _ = fs::remove_dir_all(staging_path(dir));
+    if let Err(err) = validate_lobe_chat(dir) {
+        if dir.exists() {
+            log::warn!("{}, reinstall LobeChat", err);
+            let _ = fs::remove_dir_all(dir);
+        }
+        InstallManifest::remove(app_dir, Artifact::LobeChat);
+        return false;
+    }
+    let recorded = InstallManifest::read(app_dir, Artifact::LobeChat);
+    if recorded.map(|m| m.version == version).unwrap_or(false) {
+        return true;
+    }
+    log::info!("adopt LobeChat {} at {}", version, dir.display());
+    let manifest = InstallManifest::new(Artifact::LobeChat, version, None, None);
+    if let Err(err) = manifest.save(app_dir) {
+        log::warn!("failed to save install manifest: {}", err);
+    }
+    true
+}
+
+/**
+ * 启动时检查 deno 是否完整安装并且是配置的版本
+ * deno 可能是用户自己安装的,没有安装记录时能正常运行且版本一致就补上记录
+ * 版本不一致时返回 false,重新安装时会覆盖
+ */
+pub fn check_deno(app_dir: &Path, bin: &Path, version: &str) -> bool {
+    let _ = fs::remove_dir_all(staging_path(bin));
+    if !bin.is_file() {
+        InstallManifest::remove(app_dir, Artifact::Deno);
+        return false;
+    }
+    let recorded = InstallManifest::read(app_dir, Artifact::Deno);
+    if recorded.map(|m| m.version == version).unwrap_or(false) {
+        return true;
+    }
+    InstallManifest::remove(app_dir, Artifact::Deno);
+    match validate_deno(bin) {
+        Ok(installed) if installed == version => {
+            let manifest = InstallManifest::new(Artifact::Deno, &installed, None, None);
+            if let Err(err) = manifest.save(app_dir) {
+                log::warn!("failed to save install manifest: {}", err);
+            }
+            true
+        }
+        Ok(installed) => {
+            log::warn!(
+                "deno {} at {} is not the configured version {}, reinstall deno",
+                installed,
+                bin.display(),
+                version
+            );
+            false
+        }
+        Err(err) => {
+            log::warn!("{}, reinstall deno", err);
+            let _ = fs::remove_file(bin);
+            false
+        }
+    }
+}
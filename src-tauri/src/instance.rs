@@ -0,0 +1,148 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use fs2::FileExt;
+
+use crate::error::Error;
+
+/** 单实例锁文件,进程退出后系统会自动释放锁 */
+const LOCK_FILENAME: &str = "instance.lock";
+/** 接收其他实例参数的本地 socket,windows 上保存的是监听的端口 */
+const SOCKET_FILENAME: &str = "instance.sock";
+/** 已有实例刚拿到锁时可能还没有开始监听,连接失败后重试 */
+const CONNECT_RETRIES: u32 = 20;
+const CONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[cfg(unix)]
+type Listener = std::os::unix::net::UnixListener;
+#[cfg(windows)]
+type Listener = std::net::TcpListener;
+
+/** 启动时的实例检查结果 */
+pub enum Instance {
+    /** 当前是第一个实例 */
+    Primary(InstanceGuard),
+    /** 已有实例在运行,参数已经转发过去 */
+    Secondary,
+}
+
+/** 获取到的单实例锁和接收参数的 listener */
+pub struct InstanceGuard {
+    lock: fs::File,
+    listener: Listener,
+}
+
+/** 开始接收参数后继续持有的锁,drop 后其他实例可以启动 */
+pub struct InstanceLock {
+    _lock: fs::File,
+}
+
+impl InstanceGuard {
+    /**
+     * 在后台线程中接收其他实例转发的参数
+     * 返回的锁需要一直持有,重启客户端前 drop 掉,新的进程才能拿到锁
+     */
+    pub fn listen(self, on_args: impl Fn(Vec<String>) + Send + 'static) -> InstanceLock {
+        let InstanceGuard { lock, listener } = self;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        log::warn!("failed to accept instance connection: {}", err);
+                        continue;
+                    }
+                };
+                let mut line = String::new();
+                if BufReader::new(stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                match serde_json::from_str::<Vec<String>>(&line) {
+                    Ok(args) => on_args(args),
+                    Err(err) => log::warn!("invalid instance message: {}", err),
+                }
+            }
+        });
+        InstanceLock { _lock: lock }
+    }
+}
+
+/**
+ * 获取单实例锁
+ * 已有实例在运行时把 args 发给它,调用方应直接退出
+ */
+pub fn acquire(app_dir: &Path, args: &[String]) -> Result<Instance, Error> {
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(app_dir.join(LOCK_FILENAME))?;
+    if lock.try_lock_exclusive().is_err() {
+        forward(app_dir, args)?;
+        return Ok(Instance::Secondary);
+    }
+    let listener = bind(&socket_path(app_dir))?;
+    Ok(Instance::Primary(InstanceGuard { lock, listener }))
+}
+
+fn socket_path(app_dir: &Path) -> PathBuf {
+    app_dir.join(SOCKET_FILENAME)
+}
+
+/** 把参数发给已有实例,一行 JSON 数组 */
+fn forward(app_dir: &Path, args: &[String]) -> Result<(), Error> {
+    let message = serde_json::to_string(args).map_err(|err| Error::Process(err.to_string()))?;
+    let mut attempt = 0;
+    loop {
+        match connect(&socket_path(app_dir)) {
+            Ok(mut stream) => {
+                stream.write_all(message.as_bytes())?;
+                stream.write_all(b"\n")?;
+                return Ok(());
+            }
+            Err(err) if attempt >= CONNECT_RETRIES => {
+                return Err(Error::Process(format!(
+                    "another instance is running but not reachable: {}",
+                    err
+                )))
+            }
+            Err(_) => {
+                attempt += 1;
+                thread::sleep(CONNECT_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind(path: &Path) -> Result<Listener, Error> {
+    // 上次异常退出时留下的 socket 文件,持有锁时可以放心删除
+    let _ = fs::remove_file(path);
+    Ok(std::os::unix::net::UnixListener::bind(path)?)
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> std::io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+/** windows 上监听本机的随机端口,端口写入 socket 文件 */
+#[cfg(windows)]
+fn bind(path: &Path) -> Result<Listener, Error> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    crate::config::write_atomic(path, port.to_string().as_bytes())?;
+    Ok(listener)
+}
+
+#[cfg(windows)]
+fn connect(path: &Path) -> std::io::Result<std::net::TcpStream> {
+    let port = fs::read_to_string(path)?
+        .trim()
+        .parse::<u16>()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    std::net::TcpStream::connect(("127.0.0.1", port))
+}
@@ -1,29 +1,63 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, Write};
-use std::path::{self, Path, PathBuf};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::{exit, Child, Command, Stdio};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::thread::{self, sleep};
+use std::thread;
 use std::time::Duration;
 
 use futures_util::stream::StreamExt;
 use futures_util::TryStreamExt;
-use portable_pty::{native_pty_system, CommandBuilder, PtyPair, PtySize};
+use portable_pty::{CommandBuilder, PtyPair, PtySize};
 use reqwest::blocking::get;
-use reqwest::Client;
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Deserializer, Serializer, Value};
+use tauri::webview::PageLoadEvent;
 use tauri::window;
-use tauri::PhysicalSize;
 use tauri::Size;
 #[allow(unused)]
 use tauri::{App, Emitter, Listener, Manager, RunEvent, Runtime, WebviewUrl, WebviewWindow};
+use tauri_plugin_autostart::{MacosLauncher, ManagerExt};
+use tauri_plugin_shell::ShellExt;
 use tauri_plugin_store::StoreExt;
-use thiserror;
-use zip::ZipArchive;
+use tauri_plugin_window_state::{AppHandleExt, StateFlags};
+
+pub mod cli;
+pub mod config;
+pub mod deep_link;
+mod disk;
+pub mod download;
+pub mod error;
+pub mod events;
+pub mod extract;
+pub mod http;
+pub mod install;
+mod instance;
+mod logger;
+pub mod migrations;
+pub mod navigation;
+pub mod paths;
+mod pty;
+pub mod server;
+pub mod service;
+pub mod setup;
+pub mod setup_phase;
+mod startup;
+
+use config::{ClientConfig, ConfigError};
+use download::{Artifact, CancelToken, DownloadManager};
+use error::Error;
+use events::{AppEvent, EventSink};
+use instance::Instance;
+use navigation::{Navigation, NavigationPolicy};
+use paths::Paths;
+use setup::SetupContext;
+use setup_phase::{SetupPhase, SetupStatus};
+use startup::StartupReport;
 
 struct AppState {
     /** 系统架构,用于下载 deno */
@@ -34,49 +68,65 @@ struct AppState {
      * 用于存放 LobeChat 打包文件以及配置文件
      */
     pub document_dir: PathBuf,
-    /** deno 下载地址 */
-    pub deno_zip_url: String,
     /** deno 二进制文件路径 */
     pub deno_bin: PathBuf,
-    /** 需要下载的 deno 压缩包文件路径 */
-    pub downloading_deno_zip: PathBuf,
-    /** 下载的 deno 版本 仅用于下载 */
-    pub deno_version: String,
     /** deno 是否存在 */
     pub deno_existing: bool,
-    /** LobeChat 打包产物下载地址 */
-    pub lobe_chat_zip_url: String,
     /** LobeChat 打包产物文件夹 */
     pub lobe_chat_dir: PathBuf,
     /** LobeChat 打包产物是否存在 */
     pub lobe_chat_existing: bool,
-    /** 需要下载的 LobeChat 压缩包文件路径 */
-    pub downloading_lobe_chat_zip: PathBuf,
-    /**  是否正在下载 deno */
-    pub is_downloading_deno: bool,
-    /** 是否正在下载 lobe chat  */
-    pub is_downloading_lobe_chat: bool,
     /** pty 是否已初始化 */
     pub pty_existing: bool,
-    /** pty */
-    pub pty_pair: tauri::async_runtime::Mutex<PtyPair>,
+    /** pty,创建失败时为 None */
+    pub pty_pair: Option<tauri::async_runtime::Mutex<PtyPair>>,
     // 向 pty 写入命令
-    pub writer: tauri::async_runtime::Mutex<Box<dyn io::Write + Send>>,
+    pub writer: Option<tauri::async_runtime::Mutex<Box<dyn io::Write + Send>>>,
+    /** 从 config.toml 读取的配置 */
+    pub config: ClientConfig,
+    /** 所有下载共用的 HTTP 客户端,根据 http 配置创建 */
+    pub http_client: reqwest::Client,
 }
 
-// create the error type that represents all errors possible in our program
-#[derive(Debug, thiserror::Error)]
-enum Error {
-    #[error(transparent)]
-    Io(#[from] std::io::Error),
+/** 启动参数 */
+struct LaunchOptions {
+    /** 通过 --hidden 启动,例如登录时自动启动,只在后台启动服务,不显示窗口 */
+    hidden: bool,
 }
-// we must manually implement serde::Serialize
-impl serde::Serialize for Error {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::ser::Serializer,
-    {
-        serializer.serialize_str(self.to_string().as_ref())
+
+impl LaunchOptions {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Self {
+        LaunchOptions {
+            hidden: args.any(|arg| arg == "--hidden"),
+        }
+    }
+}
+
+/** 窗口保存和恢复的状态,不恢复是否显示,由 --hidden 决定 */
+const WINDOW_STATE_FLAGS: StateFlags = StateFlags::all().difference(StateFlags::VISIBLE);
+
+/** 服务启动前收到的链接,主窗口加载完成后打开 */
+struct PendingDeepLink(Mutex<Option<String>>);
+
+/** 窗口相关的设置,窗口事件在主线程中处理,不能等待 AppState 的锁 */
+struct WindowSettings(Mutex<config::WindowConfig>);
+
+/** 单实例锁,重启前释放 */
+struct SingleInstance(Mutex<Option<instance::InstanceLock>>);
+
+/** 客户端启动的 LobeChat 服务进程,退出客户端时结束 */
+#[derive(Default)]
+struct ServerProcess(Mutex<Option<Child>>);
+
+impl ServerProcess {
+    /** 结束服务进程并删除 pid 文件,服务未启动时不做任何事 */
+    fn stop(&self, app_dir: &Path) {
+        if let Some(mut child) = self.0.lock().unwrap().take() {
+            log::info!("stop LobeChat server with pid {}", child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            server::remove_pid(app_dir);
+        }
     }
 }
 
@@ -105,244 +155,663 @@ impl BizResponse {
     }
 }
 
-fn get_document_dir() -> PathBuf {
-    let mut document_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
-    let application_dir: PathBuf = document_dir.to_path_buf().join(".lobe_chat_client");
-    if !fs::metadata(application_dir.clone()).is_ok() {
-        fs::create_dir_all(application_dir.clone());
-    }
-    application_dir
-}
 fn is_directory_exists(dir: &PathBuf) -> bool {
     fs::metadata(&dir)
         .map(|meta| meta.is_dir())
         .unwrap_or(false)
 }
-fn get_file_parent_directory(dir: &PathBuf) -> Option<PathBuf> {
-    dir.parent().map(|p| p.to_path_buf())
+
+/** 修改初始化阶段并通知前端 */
+fn set_setup_phase(app: &tauri::AppHandle, phase: SetupPhase) {
+    app.state::<SetupStatus>().set(phase.clone());
+    let _ = app.emit("setup_phase_changed", &phase);
+    // 后台启动时初始化失败需要显示窗口,否则用户无从得知
+    if matches!(phase, SetupPhase::Failed { .. }) && app.state::<LaunchOptions>().hidden {
+        if let Some(window) = app.get_webview_window("setup") {
+            let _ = window.show();
+        }
+    }
 }
 
-fn extract_zip(zip_path: &PathBuf, output_folder: &PathBuf) -> Result<String, String> {
-    let zip_file = fs::File::open(zip_path).map_err(|e| e.to_string())?;
-    let mut archive = ZipArchive::new(zip_file).map_err(|e| e.to_string())?;
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
-        let out_path = path::Path::new(&output_folder).join(file.sanitized_name());
+/** 判断服务是否启动时保留的输出长度,足够容纳被拆到两次输出中的访问地址 */
+const SERVER_OUTPUT_TAIL: usize = 1024;
 
-        // 确保输出目录存在
-        if let Some(parent) = out_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+/** 正在启动的服务,访问地址由配置的端口决定 */
+struct StartingServer {
+    url: String,
+    /** 最近的输出,只保留末尾的 SERVER_OUTPUT_TAIL 字节 */
+    output: String,
+}
+
+/**
+ * 将核心逻辑的通知转为前端事件
+ * 服务启动阶段输出中出现访问地址后进入 Ready 阶段
+ */
+struct TauriEvents {
+    app: tauri::AppHandle,
+    starting_server: Mutex<Option<StartingServer>>,
+}
+
+impl TauriEvents {
+    fn new(app: tauri::AppHandle) -> Self {
+        TauriEvents {
+            app,
+            starting_server: Mutex::new(None),
         }
-        // 解压文件
-        if file.is_dir() {
-            std::fs::create_dir_all(&out_path).map_err(|e| e.to_string())?;
-        } else {
-            let mut out_file = fs::File::create(&out_path).map_err(|e| e.to_string())?;
-            io::copy(&mut file, &mut out_file).map_err(|e| e.to_string())?;
+    }
+
+    /** 开始等待服务启动 */
+    fn server_starting(&self, url: String) {
+        *self.starting_server.lock().unwrap() = Some(StartingServer {
+            url,
+            output: String::new(),
+        });
+    }
+
+    /** 输出中出现 Local: 地址时认为服务已经可以访问,地址本身只作参考 */
+    fn check_server_ready(&self, data: &[u8]) {
+        let mut starting_server = self.starting_server.lock().unwrap();
+        let starting = match starting_server.as_mut() {
+            Some(starting) => starting,
+            None => return,
+        };
+        if self.app.state::<SetupStatus>().get() != SetupPhase::StartingServer {
+            *starting_server = None;
+            return;
+        }
+        starting.output.push_str(&String::from_utf8_lossy(data));
+        if let Some(url) = setup_phase::find_server_url(&starting.output) {
+            if url.trim_end_matches('/') != starting.url {
+                log::warn!("server prints {} but listens on {}", url, starting.url);
+            }
+            let url = starting.url.clone();
+            *starting_server = None;
+            set_setup_phase(&self.app, SetupPhase::Ready { url });
+            return;
+        }
+        if starting.output.len() > SERVER_OUTPUT_TAIL {
+            let mut start = starting.output.len() - SERVER_OUTPUT_TAIL;
+            while !starting.output.is_char_boundary(start) {
+                start += 1;
+            }
+            starting.output.drain(..start);
         }
     }
-    return Ok(format!("ok"));
 }
 
-async fn download_deno(
-    app: tauri::AppHandle,
-    window: tauri::WebviewWindow,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let state1 = app.state::<tokio::sync::Mutex<AppState>>();
-    let mut state = state1.lock().await;
-
-    println!("[COMMAND]download_deno");
-
-    let deno_version = &state.deno_version;
-    let target = &state.os_target;
-    let deno_uri = String::from(&state.deno_zip_url);
-    let deno_bin_filepath = PathBuf::from(&state.deno_bin);
-    let deno_zip_filepath = PathBuf::from(&state.downloading_deno_zip);
-
-    if !deno_zip_filepath.exists() {
-        window.emit(
-            "deno_download_start",
-            json!({
-                "uri": &deno_uri,
-                "target": deno_zip_filepath.display().to_string(),
-            }),
-        );
-        state.is_downloading_deno = true;
-        let client = Client::new();
-        let mut response = client.get(&deno_uri).send().await?;
-        if !response.status().is_success() {
-            window.emit(
-                "deno_download_failed",
-                json!({ "reason": "request failed", "url": &deno_uri, "filepath": &deno_zip_filepath.display().to_string() }),
-            );
-            return Ok(());
+impl EventSink for TauriEvents {
+    fn emit(&self, event: AppEvent) {
+        let app = &self.app;
+        match event {
+            AppEvent::Phase(phase) => set_setup_phase(app, phase),
+            AppEvent::Retrying {
+                url,
+                attempt,
+                max_retries,
+                delay,
+                reason,
+            } => {
+                let _ = app.emit(
+                    "download_retrying",
+                    json!({
+                        "url": url,
+                        "attempt": attempt,
+                        "max_retries": max_retries,
+                        "delay": delay.as_millis() as u64,
+                        "reason": reason,
+                    }),
+                );
+            }
+            AppEvent::ExtractProgress { file, progress } => {
+                let _ = app.emit(
+                    "extract_progress",
+                    json!({
+                        "file": file,
+                        "entries_done": progress.entries_done,
+                        "entries_total": progress.entries_total,
+                        "bytes_done": progress.bytes_done,
+                    }),
+                );
+            }
+            AppEvent::TermData(data) => {
+                self.check_server_ready(&data);
+                let _ = app.emit("term_data", data);
+            }
+            AppEvent::PtyError(err) => {
+                let _ = app.emit("pty_error", &err);
+            }
         }
-        let mut file = fs::File::create(&deno_zip_filepath).expect("Unable to create file");
-        let mut writer = io::BufWriter::new(file);
-        let mut downloaded: u64 = 0;
-        let total_size = response
-            .content_length()
-            .ok_or("Could not get content length")?;
-
-        let mut stream = response.bytes_stream();
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            downloaded += chunk.len() as u64;
-            writer.write_all(&chunk)?;
-            let percentage = downloaded as f64 / total_size as f64 * 100.0;
-            // let msg = format!("Download progress: {:.2}%", percentage);
-            window.emit("deno_download_percent", json!({ "percent": percentage }));
+    }
+}
+
+/** 根据设置注册或取消登录时自动启动,启动时带上 --hidden 参数 */
+fn apply_autostart(app: &tauri::AppHandle, enabled: bool) -> Result<(), Error> {
+    let autolaunch = app.autolaunch();
+    let current = autolaunch
+        .is_enabled()
+        .map_err(|err| Error::Process(err.to_string()))?;
+    if current == enabled {
+        return Ok(());
+    }
+    let r = if enabled {
+        autolaunch.enable()
+    } else {
+        autolaunch.disable()
+    };
+    r.map_err(|err| Error::Process(format!("failed to update autostart: {}", err)))
+}
+
+/**
+ * 显示已有的窗口,后台启动后用户再次打开客户端时使用
+ * 服务已经启动但主窗口不存在时重新创建主窗口
+ */
+fn show_windows(app: &tauri::AppHandle) {
+    let mut window = app
+        .get_webview_window("main")
+        .or_else(|| app.get_webview_window("setup"));
+    if window.is_none() {
+        if let SetupPhase::Ready { url } = app.state::<SetupStatus>().get() {
+            window = main_window(app, &url)
+                .map_err(|err| log::warn!("failed to create main window: {}", err))
+                .ok();
         }
-        writer.flush()?;
-        state.is_downloading_deno = false;
     }
-    if !deno_bin_filepath.exists() {
-        window.emit(
-            "unzip_deno",
-            json!({"file": &deno_zip_filepath.display().to_string()}),
-        );
-        let r1 = fs::File::open(&deno_zip_filepath);
-        if r1.is_err() {
-            fs::remove_file(&deno_zip_filepath);
-            window.emit(
-                "deno_download_failed",
-                json!({ "reason": "open deno zip file failed", "url": &deno_uri, "filepath": &deno_zip_filepath.display().to_string() }),
-            );
-            return Ok(());
+    if let Some(window) = window {
+        focus_window(&window);
+    }
+}
+
+/** 托盘图标的 id */
+const TRAY_ID: &str = "main";
+
+/**
+ * 托盘图标,主窗口关闭后隐藏时通过它重新显示或退出客户端
+ * macOS 通过 Dock 图标显示窗口,不需要托盘
+ */
+#[cfg(not(target_os = "macos"))]
+fn create_tray(app: &tauri::App) -> tauri::Result<()> {
+    use tauri::menu::{Menu, MenuItem};
+    use tauri::tray::TrayIconBuilder;
+
+    let show = MenuItem::with_id(app, "show", "显示窗口", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, "quit", "退出", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&show, &quit])?;
+    let mut tray = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("LobeChat")
+        .menu(&menu)
+        .on_menu_event(|app, event| match event.id().as_ref() {
+            "show" => show_windows(app),
+            "quit" => app.exit(0),
+            _ => {}
+        });
+    if let Some(icon) = app.default_window_icon() {
+        tray = tray.icon(icon.clone());
+    }
+    tray.build(app)?;
+    Ok(())
+}
+
+/** 隐藏主窗口后能否再找回来,macOS 可以点击 Dock 图标,其他平台需要托盘图标 */
+fn can_hide_main_window(app: &tauri::AppHandle) -> bool {
+    cfg!(target_os = "macos") || app.tray_by_id(TRAY_ID).is_some()
+}
+
+fn focus_window(window: &WebviewWindow) {
+    let _ = window.show();
+    let _ = window.unminimize();
+    let _ = window.set_focus();
+}
+
+fn parse_url(url: &str) -> Result<tauri::Url, Error> {
+    url.parse()
+        .map_err(|err| Error::Process(format!("invalid url {}: {}", url, err)))
+}
+
+/**
+ * 获取主窗口,不存在时打开 url 创建一个隐藏的主窗口,由调用方决定是否显示
+ * 创建时 window-state 插件会恢复上次保存的位置和大小,没有保存过时使用默认大小,尺寸都是逻辑像素
+ * 只能在主窗口中打开本地服务和白名单中的地址
+ */
+fn main_window(app: &tauri::AppHandle, url: &str) -> Result<WebviewWindow, Error> {
+    if let Some(window) = app.get_webview_window("main") {
+        return Ok(window);
+    }
+    let app_handle = app.clone();
+    let window = WebviewWindow::builder(app, "main", WebviewUrl::External(parse_url(url)?))
+        .title("LobeChatClient")
+        .inner_size(1280.0, 880.0)
+        .min_inner_size(800.0, 600.0)
+        .center()
+        .visible(false)
+        .on_navigation(move |url| handle_navigation(&app_handle, url))
+        .build()
+        .map_err(|err| Error::Process(err.to_string()))?;
+    Ok(window)
+}
+
+/**
+ * 主窗口跳转前调用,返回 false 时不在主窗口中打开
+ * 本地服务之外的 http(s) 地址交给系统浏览器,服务未就绪时一律拒绝
+ */
+fn handle_navigation(app: &tauri::AppHandle, url: &tauri::Url) -> bool {
+    let server = match app.state::<SetupStatus>().get() {
+        SetupPhase::Ready { url } => url.parse().ok(),
+        _ => None,
+    };
+    let server = match server {
+        Some(server) => server,
+        None => {
+            log::warn!("blocked navigation to {} before the server is ready", url);
+            return false;
         }
-        let file = r1.unwrap();
-        let r2 = ZipArchive::new(file);
-        if r2.is_err() {
-            fs::remove_file(&deno_zip_filepath);
-            window.emit(
-                "deno_download_failed",
-                json!({ "reason": "open file with zip archive failed", "url": &deno_uri, "filepath": &deno_zip_filepath.display().to_string() }),
-            );
-            return Ok(());
+    };
+    let allowlist = app
+        .state::<WindowSettings>()
+        .0
+        .lock()
+        .unwrap()
+        .navigation_allowlist
+        .clone();
+    match NavigationPolicy::new(server, &allowlist).check(url) {
+        Navigation::Allow => true,
+        Navigation::External => {
+            log::info!("open {} in system browser", url);
+            if let Err(err) = app.shell().open(url.to_string(), None) {
+                log::warn!("failed to open {}: {}", url, err);
+            }
+            false
         }
-        let mut archive = r2.unwrap();
-        let r3 = archive.extract(&deno_bin_filepath.parent().unwrap());
-        // let r = extract_zip(&deno_zip_filepath, &deno_bin_filepath);
-        if r3.is_err() {
-            fs::remove_file(&deno_zip_filepath);
-            window.emit("deno_download_failed", json!({ "reason": "unzip failed", "filepath": &deno_zip_filepath.display().to_string() }));
-            return Ok(());
+        Navigation::Deny => {
+            log::warn!("blocked navigation to {}", url);
+            false
         }
-        // fs::remove_file(zip_path).expect("Failed to delete ZIP file");
-        write_to_pty(format!("chmod -x {}", &deno_bin_filepath.display().to_string()), state1.clone());
-        // Command::new("chmod")
-        //     .arg("+x")
-        //     .arg(&deno_bin_filepath)
-        //     .output()
-        //     .unwrap();
-        window.set_focus();
-    }
-    window.emit(
-        "can_download_lobe_chat",
-        json!({"bin_path": deno_bin_filepath.display().to_string()}),
-    );
-    return Ok(());
+    }
+}
+
+/** 在主窗口中打开地址,主窗口不存在时重新创建 */
+fn open_in_main_window(app: &tauri::AppHandle, url: &str) -> Result<(), Error> {
+    let window = match app.get_webview_window("main") {
+        Some(window) => {
+            window
+                .navigate(parse_url(url)?)
+                .map_err(|err| Error::Process(err.to_string()))?;
+            window
+        }
+        None => main_window(app, url)?,
+    };
+    focus_window(&window);
+    Ok(())
+}
+
+/**
+ * 打开 lobechat:// 链接
+ * 服务还没有启动时先记录下来,主窗口加载完成后再跳转
+ */
+fn open_deep_link(app: &tauri::AppHandle, link: &str) {
+    log::info!("open deep link {}", link);
+    let server_url = match app.state::<SetupStatus>().get() {
+        SetupPhase::Ready { url } => url,
+        _ => {
+            *app.state::<PendingDeepLink>().0.lock().unwrap() = Some(String::from(link));
+            show_windows(app);
+            return;
+        }
+    };
+    let r = deep_link::resolve(link, &server_url).and_then(|url| open_in_main_window(app, &url));
+    if let Err(err) = r {
+        log::warn!("failed to open deep link: {}", err);
+    }
+}
+
+/** 主窗口第一次加载完成后打开启动时收到的链接 */
+fn open_pending_deep_link(window: &tauri::Webview) {
+    let link = window.state::<PendingDeepLink>().0.lock().unwrap().take();
+    let (link, server_url) = match (link, window.state::<SetupStatus>().get()) {
+        (Some(link), SetupPhase::Ready { url }) => (link, url),
+        _ => return,
+    };
+    let r = deep_link::resolve(&link, &server_url).and_then(|url| {
+        window
+            .navigate(parse_url(&url)?)
+            .map_err(|err| Error::Process(err.to_string()))
+    });
+    if let Err(err) = r {
+        log::warn!("failed to open deep link: {}", err);
+    }
+}
+
+/** 处理其他实例转发过来的启动参数 */
+fn handle_forwarded_args(app: &tauri::AppHandle, args: Vec<String>) {
+    log::info!("received args from another instance: {:?}", args);
+    if let Some(link) = deep_link::find(&args) {
+        open_deep_link(app, link);
+        return;
+    }
+    // 再次通过自动启动拉起时保持当前的窗口状态
+    if LaunchOptions::from_args(args.into_iter()).hidden {
+        return;
+    }
+    show_windows(app);
+}
+
+/** 根据当前的配置创建 SetupContext */
+fn setup_context(app: &tauri::AppHandle, state: &AppState) -> SetupContext {
+    SetupContext::new(
+        Paths::new(&state.document_dir, &state.os_target, &state.config),
+        &state.os_target,
+        state.config.clone(),
+        state.http_client.clone(),
+        app.state::<Arc<TauriEvents>>().inner().clone(),
+    )
+}
+
+/** 下载并解压 deno,完成后继续准备 LobeChat */
+async fn download_deno(app: tauri::AppHandle, token: CancelToken) -> Result<(), Error> {
+    log::info!("[COMMAND]download_deno");
+    let r = install_deno(&app, &token).await;
+    app.state::<DownloadManager>().finish(Artifact::Deno);
+    r?;
+    // LobeChat 已经在下载时使用已有任务的进度
+    match app.state::<DownloadManager>().claim(Artifact::LobeChat) {
+        Some(token) => download_zip_file_then_unzip(app, token).await,
+        None => Ok(()),
+    }
+}
+
+async fn install_deno(app: &tauri::AppHandle, token: &CancelToken) -> Result<(), Error> {
+    let (ctx, deno_existing) = {
+        let state = app.state::<tokio::sync::Mutex<AppState>>();
+        let state = state.lock().await;
+        (setup_context(app, &state), state.deno_existing)
+    };
+    if !deno_existing {
+        ctx.install_deno(token).await?;
+    }
+    app.state::<tokio::sync::Mutex<AppState>>()
+        .lock()
+        .await
+        .deno_existing = true;
+    Ok(())
 }
 
+/** 下载并解压 LobeChat 打包产物,完成后进入启动服务阶段 */
 async fn download_zip_file_then_unzip(
-    url: String,
     app: tauri::AppHandle,
-    window: tauri::WebviewWindow,
-) -> Result<(), Box<dyn std::error::Error>> {
+    token: CancelToken,
+) -> Result<(), Error> {
+    let r = install_lobe_chat(&app, &token).await;
+    app.state::<DownloadManager>().finish(Artifact::LobeChat);
+    r?;
+    set_setup_phase(&app, SetupPhase::StartingServer);
+    start_server(&app).await
+}
+
+/**
+ * 启动 LobeChat 服务,与命令行的 serve 使用相同的命令和环境变量文件
+ * 输出和终端一样通过 term_data 发出,从中判断服务是否已经启动
+ * 已有运行中的服务时直接使用,服务退出时进入 Failed 阶段
+ */
+async fn start_server(app: &tauri::AppHandle) -> Result<(), Error> {
     let state = app.state::<tokio::sync::Mutex<AppState>>();
-    let mut state = state.lock().await;
-    // println!("_download file from {} to {:?}", url, filepath);
-    let lobe_chat_zip_filepath = PathBuf::from(&state.downloading_lobe_chat_zip);
-    if !lobe_chat_zip_filepath.exists() {
-        window.emit(
-            "lobe_chat_download_start",
-            json!({"uri": url, "target": lobe_chat_zip_filepath}),
-        );
-        let client = Client::new();
-        state.is_downloading_lobe_chat = true;
-        let mut response = client.get(url).send().await?;
-        if !response.status().is_success() {
-            // println!("Failed to download file: {}", response.status());
-            window.emit(
-                "lobe_chat_download_failed",
-                json!({"reason": "request failed", "filepath": &lobe_chat_zip_filepath.display().to_string() }),
-            );
-            return Ok(());
-        }
-        let mut downloaded: u64 = 0;
-        let file = fs::File::create(&lobe_chat_zip_filepath)?;
-        let mut writer = io::BufWriter::new(file);
-        let total_size = response
-            .content_length()
-            .ok_or("Could not get content length")?;
-        let mut stream = response.bytes_stream();
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            downloaded += chunk.len() as u64;
-            writer.write_all(&chunk)?;
-            let percentage = downloaded as f64 / total_size as f64 * 100.0;
-            // let msg = format!("Download progress: {:.2}%", percentage);
-            window.emit(
-                "lobe_chat_download_percent",
-                json!({ "percent": percentage }),
-            );
-        }
-        writer.flush()?;
-        state.is_downloading_lobe_chat = false;
-    }
-    let lobe_chat_dir = &state.lobe_chat_dir;
-    if !lobe_chat_dir.exists() {
-        window.emit(
-            "unzip_lobe_chat",
-            json!({ "file": &lobe_chat_zip_filepath.display().to_string() }),
-        );
-        let r = extract_zip(&lobe_chat_zip_filepath, &lobe_chat_dir);
-        if r.is_err() {
-            fs::remove_file(&lobe_chat_zip_filepath);
-            window.emit(
-                "lobe_chat_download_failed",
-                json!({ "reason": "unzip failed", "filepath": &lobe_chat_zip_filepath.display().to_string() }),
-            );
-            return Ok(());
-        }
+    let state = state.lock().await;
+    let process = app.state::<ServerProcess>();
+    let mut process = process.0.lock().unwrap();
+    if process.is_some() {
+        return Ok(());
+    }
+    let url = server::url(state.config.server.port);
+    // 命令行的 serve 启动的服务,退出客户端时不结束
+    if let Some(pid) = server::running_pid(&state.document_dir) {
+        log::info!("reuse LobeChat server with pid {} at {}", pid, url);
+        set_setup_phase(app, SetupPhase::Ready { url });
+        return Ok(());
     }
-    window.emit(
-        "can_start_lobe_chat_server",
-        json!({"lobe_chat_path": &lobe_chat_dir.display().to_string()}),
+    let mut command = server::command(
+        &state.deno_bin,
+        &state.lobe_chat_dir,
+        state.config.server.port,
     );
+    if let Some(env_file) = state.config.server.env_file.as_ref() {
+        command.envs(server::read_env_file(env_file)?);
+    }
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            Error::Process(format!(
+                "failed to start {}: {}",
+                state.deno_bin.display(),
+                err
+            ))
+        })?;
+    let events = app.state::<Arc<TauriEvents>>().inner().clone();
+    events.server_starting(url);
+    let stdout = child
+        .stdout
+        .take()
+        .map(|output| Box::new(output) as Box<dyn io::Read + Send>);
+    let stderr = child
+        .stderr
+        .take()
+        .map(|output| Box::new(output) as Box<dyn io::Read + Send>);
+    for output in [stdout, stderr].into_iter().flatten() {
+        let events = events.clone();
+        thread::spawn(move || pty::forward_output(&mut io::BufReader::new(output), &*events));
+    }
+    // 命令行的 status 和 stop 通过 pid 文件找到服务
+    if let Err(err) = server::write_pid(&state.document_dir, child.id()) {
+        log::warn!("failed to write server pid: {}", err);
+    }
+    log::info!("LobeChat server started with pid {}", child.id());
+    watch_server(app.clone(), child.id(), state.document_dir.clone());
+    *process = Some(child);
+    Ok(())
+}
+
+/** 定期检查服务进程,意外退出时进入 Failed 阶段,通过 ServerProcess::stop 结束时不处理 */
+fn watch_server(app: tauri::AppHandle, pid: u32, app_dir: PathBuf) {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_millis(500));
+        let process = app.state::<ServerProcess>();
+        let mut process = process.0.lock().unwrap();
+        let child = match process.as_mut() {
+            Some(child) if child.id() == pid => child,
+            _ => return,
+        };
+        let message = match child.try_wait() {
+            Ok(None) => continue,
+            Ok(Some(status)) => format!("LobeChat server exited with {}", status),
+            Err(err) => format!("failed to wait for LobeChat server: {}", err),
+        };
+        *process = None;
+        drop(process);
+        server::remove_pid(&app_dir);
+        let err = Error::Process(message);
+        log::error!("{}", err);
+        set_setup_phase(&app, SetupPhase::Failed { error: json!(err) });
+        return;
+    });
+}
+
+async fn install_lobe_chat(app: &tauri::AppHandle, token: &CancelToken) -> Result<(), Error> {
+    let (ctx, lobe_chat_existing) = {
+        let state = app.state::<tokio::sync::Mutex<AppState>>();
+        let state = state.lock().await;
+        (setup_context(app, &state), state.lobe_chat_existing)
+    };
+    if !lobe_chat_existing {
+        ctx.install_lobe_chat(token).await?;
+    }
+    app.state::<tokio::sync::Mutex<AppState>>()
+        .lock()
+        .await
+        .lobe_chat_existing = true;
     return Ok(());
 }
 
+/** 在 tauri 的异步运行时中执行初始化任务,失败时进入 Failed 阶段 */
+fn spawn_setup_task<F, Fut>(app: tauri::AppHandle, task: F)
+where
+    F: FnOnce(tauri::AppHandle) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), Error>> + Send + 'static,
+{
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = task(app.clone()).await {
+            log::error!("{}", err);
+            set_setup_phase(&app, SetupPhase::Failed { error: json!(err) });
+        }
+    });
+}
+
+/**
+ * 开始下载,返回值中的 attached 表示已有同一文件的下载任务
+ * 此时不会重新下载,调用方通过 setup_phase_changed 事件获取已有任务的进度
+ */
+async fn start_download<F, Fut>(
+    app: tauri::AppHandle,
+    artifact: Artifact,
+    task: F,
+) -> Result<serde_json::Value, Error>
+where
+    F: FnOnce(tauri::AppHandle, CancelToken) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let token = app.state::<DownloadManager>().claim(artifact);
+    let attached = token.is_none();
+    if let Some(token) = token {
+        spawn_setup_task(app.clone(), move |app| task(app, token));
+    }
+    let phase = app.state::<SetupStatus>().get();
+    Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": json!({ "attached": attached, "phase": phase }),
+    }))
+}
+
 #[tauri::command]
-async fn download_lobe_chat(
+async fn download_lobe_chat(app: tauri::AppHandle) -> Result<serde_json::Value, Error> {
+    // deno 的任务完成后会继续下载 LobeChat
+    if app.state::<DownloadManager>().is_running(Artifact::Deno) {
+        return start_download(app, Artifact::Deno, download_deno).await;
+    }
+    start_download(app, Artifact::LobeChat, download_zip_file_then_unzip).await
+}
+
+/**
+ * 取消下载
+ * keep_partial 为 true 时保留已下载的部分,下次从中断处继续,不传时使用设置中的值
+ */
+#[tauri::command]
+async fn cancel_download(
+    kind: Artifact,
+    keep_partial: Option<bool>,
+    downloads: tauri::State<'_, DownloadManager>,
     state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-    app: tauri::AppHandle,
-    window: tauri::WebviewWindow,
 ) -> Result<serde_json::Value, Error> {
-    let state = state.lock().await;
-    let cloned_app = app.clone();
-    let cloned_window = window.clone();
-    let url_clone = state.lobe_chat_zip_url.clone();
-    thread::spawn(move || {
-        let _ = tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(download_zip_file_then_unzip(
-                url_clone,
-                cloned_app,
-                cloned_window,
-            ));
-    });
+    let keep_partial = match keep_partial {
+        Some(keep_partial) => keep_partial,
+        None => state.lock().await.config.download.keep_partial,
+    };
+    if !downloads.cancel(kind, keep_partial) {
+        return Err(Error::NotFound(format!("{} download", kind.name())));
+    }
+    Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": serde_json::Value::Null,
+    }))
+}
+
+/** 获取当前的初始化阶段 */
+#[tauri::command]
+async fn get_setup_status(
+    status: tauri::State<'_, SetupStatus>,
+) -> Result<serde_json::Value, Error> {
     return Ok(json!({
         "code": 0,
-        "msg": "start download file",
-        "data": None::<String>,
+        "msg": "",
+        "data": status.get(),
     }));
 }
 
+/** 在 PATH 中查找可执行文件,传入的是路径时直接判断文件是否存在 */
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let candidate = PathBuf::from(name);
+    if candidate.components().count() > 1 {
+        return if candidate.is_file() {
+            Some(candidate)
+        } else {
+            None
+        };
+    }
+    let paths = env::var_os("PATH")?;
+    for dir in env::split_paths(&paths) {
+        let full_path = dir.join(name);
+        if full_path.is_file() {
+            return Some(full_path);
+        }
+        #[cfg(target_os = "windows")]
+        {
+            let with_ext = dir.join(format!("{}.exe", name));
+            if with_ext.is_file() {
+                return Some(with_ext);
+            }
+        }
+    }
+    None
+}
+
+/**
+ * 决定 pty 使用的 shell
+ * 优先级为 调用方传入 > 用户设置 > $SHELL > 平台默认,bash 不存在时(如 Alpine)回退到 sh
+ */
+fn resolve_shell(requested: Option<String>, setting: Option<String>) -> Result<String, Error> {
+    if let Some(shell) = requested.filter(|s| !s.trim().is_empty()) {
+        if find_executable(&shell).is_none() {
+            return Err(Error::NotFound(format!("shell `{}`", shell)));
+        }
+        return Ok(shell);
+    }
+    let mut candidates: Vec<String> = vec![];
+    if let Some(shell) = setting.filter(|s| !s.trim().is_empty()) {
+        candidates.push(shell);
+    }
+    #[cfg(not(target_os = "windows"))]
+    if let Ok(shell) = env::var("SHELL") {
+        if !shell.trim().is_empty() {
+            candidates.push(shell);
+        }
+    }
+    #[cfg(target_os = "windows")]
+    candidates.push(String::from("powershell.exe"));
+    #[cfg(not(target_os = "windows"))]
+    {
+        candidates.push(String::from("bash"));
+        candidates.push(String::from("sh"));
+    }
+    for shell in candidates.iter() {
+        if find_executable(shell).is_some() {
+            return Ok(shell.clone());
+        }
+    }
+    Err(Error::NotFound(format!(
+        "shell (tried `{}`)",
+        candidates.join("`, `")
+    )))
+}
+
 #[tauri::command]
 async fn start_pty(
+    shell: Option<String>,
+    args: Option<Vec<String>>,
+    cwd: Option<PathBuf>,
+    env: Option<HashMap<String, String>>,
+    app: tauri::AppHandle,
     state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-) -> Result<serde_json::Value, String> {
+) -> Result<serde_json::Value, Error> {
     let mut state = state.lock().await;
     if state.pty_existing == true {
         return Ok(json!({
@@ -352,27 +821,55 @@ async fn start_pty(
         }));
     }
 
-    #[cfg(target_os = "windows")]
-    let mut cmd = CommandBuilder::new("powershell.exe");
+    // 用户在设置中指定的 shell
+    let shell = resolve_shell(shell, state.config.pty.shell.clone())?;
+
+    let mut cmd = CommandBuilder::new(&shell);
+    if let Some(args) = args {
+        cmd.args(args);
+    }
+    if let Some(cwd) = cwd {
+        if !is_directory_exists(&cwd) {
+            return Err(Error::NotFound(format!(
+                "working directory `{}`",
+                cwd.display()
+            )));
+        }
+        cmd.cwd(cwd);
+    }
     #[cfg(target_os = "windows")]
     cmd.env("TERM", "cygwin");
     #[cfg(not(target_os = "windows"))]
-    let mut cmd = CommandBuilder::new("bash");
-    #[cfg(not(target_os = "windows"))]
     cmd.env("TERM", "xterm-256color");
+    if let Some(env) = env {
+        for (key, value) in env.iter() {
+            cmd.env(key, value);
+        }
+    }
 
     let mut child = state
         .pty_pair
+        .as_ref()
+        .ok_or_else(|| Error::Pty(String::from("pty is not available")))?
         .lock()
         .await
         .slave
         .spawn_command(cmd)
-        .map_err(|err| err.to_string())?;
+        .map_err(|err| Error::Pty(format!("failed to spawn `{}`: {}", shell, err)))?;
     state.pty_existing = true;
 
+    // 终端退出时客户端随之退出,先结束服务进程
+    let app_dir = state.document_dir.clone();
     thread::spawn(move || {
-        let status = child.wait().unwrap();
-        exit(status.exit_code() as i32)
+        let code = match child.wait() {
+            Ok(status) => status.exit_code() as i32,
+            Err(err) => {
+                log::error!("{}", Error::Process(err.to_string()));
+                1
+            }
+        };
+        app.state::<ServerProcess>().stop(&app_dir);
+        exit(code)
     });
 
     return Ok(json!({
@@ -386,10 +883,15 @@ async fn start_pty(
 async fn write_to_pty(
     data: String,
     state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-) -> Result<(), ()> {
+) -> Result<(), Error> {
     let state = state.lock().await;
-    let mut w = state.writer.lock().await;
-    write!(w, "{}", data).map_err(|_| ());
+    let mut w = state
+        .writer
+        .as_ref()
+        .ok_or_else(|| Error::Pty(String::from("pty is not available")))?
+        .lock()
+        .await;
+    write!(w, "{}", data).map_err(|err| Error::Pty(err.to_string()))?;
     return Ok(());
 }
 
@@ -398,11 +900,13 @@ async fn resize_pty(
     rows: u16,
     cols: u16,
     state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-) -> Result<serde_json::Value, ()> {
+) -> Result<serde_json::Value, Error> {
     let state = state.lock().await;
 
     state
         .pty_pair
+        .as_ref()
+        .ok_or_else(|| Error::Pty(String::from("pty is not available")))?
         .lock()
         .await
         .master
@@ -411,7 +915,7 @@ async fn resize_pty(
             cols,
             ..Default::default()
         })
-        .map_err(|_| ());
+        .map_err(|err| Error::Pty(err.to_string()))?;
 
     return Ok(json!({
         "code": 0,
@@ -421,56 +925,55 @@ async fn resize_pty(
 }
 
 #[tauri::command]
-fn show_main_window(url: String, app: tauri::AppHandle) -> Result<serde_json::Value, ()> {
-    // println!("show main window with url {}", url);
-    let setup_window = app.get_webview_window("setup").unwrap();
-    setup_window.close().unwrap();
-
-    let main_window = WebviewWindow::builder(&app, "main", tauri::WebviewUrl::App(url.into()))
-        .build()
-        .unwrap();
-    main_window.set_size(PhysicalSize::new(1280, 880));
-    main_window.set_title("LobeChatClient");
-    main_window.show();
-
-    // let main_window = WebviewWindowBuilder::new(&app, "main", tauri::WebviewUrl::App(url.into())).build().unwrap();
-    // main_window.show();
-
-    // thread::spawn(move || {
-    //     let _ = tokio::runtime::Runtime::new().unwrap().block_on(show_main_window(app_clone, url));
-    // });
-    // return Ok(());
-    return Ok(json!({
+fn show_main_window(
+    url: String,
+    app: tauri::AppHandle,
+    launch: tauri::State<'_, LaunchOptions>,
+) -> Result<serde_json::Value, Error> {
+    // 主窗口只能在服务就绪后打开本地服务
+    let server_url = match app.state::<SetupStatus>().get() {
+        SetupPhase::Ready { url } => url,
+        phase => {
+            return Err(Error::Process(format!(
+                "LobeChat server is not ready, current phase is {:?}",
+                phase
+            )))
+        }
+    };
+    if parse_url(&url)?.origin() != parse_url(&server_url)?.origin() {
+        return Err(Error::Process(format!(
+            "{} is not the local server {}",
+            url, server_url
+        )));
+    }
+    // 已经有主窗口时直接使用,重复调用不会再创建
+    let window = main_window(&app, &url)?;
+    if !launch.hidden {
+        focus_window(&window);
+    }
+    // 先创建主窗口再关闭初始化窗口,避免没有窗口时应用退出
+    if let Some(setup_window) = app.get_webview_window("setup") {
+        setup_window
+            .close()
+            .map_err(|err| Error::Process(err.to_string()))?;
+    }
+    Ok(json!({
         "code": 0,
         "msg": "",
         "data": serde_json::Value::Null,
-    }));
+    }))
 }
 
 #[tauri::command]
-fn download_deno_then_enable(
-    app: tauri::AppHandle,
-    state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-) -> Result<serde_json::Value, ()> {
-    let window = app.get_webview_window("setup").unwrap();
-    let cloned_app = app.clone();
-    let cloned_window = window.clone();
-    thread::spawn(move || {
-        let _ = tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(download_deno(cloned_app, cloned_window));
-    });
-    return Ok(json!({
-        "code": 0,
-        "msg": "",
-        "data": serde_json::Value::Null,
-    }));
+async fn download_deno_then_enable(app: tauri::AppHandle) -> Result<serde_json::Value, Error> {
+    start_download(app, Artifact::Deno, download_deno).await
 }
 
 #[tauri::command]
 async fn fetch_setup_config(
+    launch: tauri::State<'_, LaunchOptions>,
     state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
-) -> Result<serde_json::Value, ()> {
+) -> Result<serde_json::Value, Error> {
     let state = state.lock().await;
     return Ok(json!({
         "code": 0,
@@ -480,167 +983,374 @@ async fn fetch_setup_config(
             "deno_existing": state.deno_existing,
             "lobe_chat_dir": state.lobe_chat_dir,
             "lobe_chat_existing": state.lobe_chat_existing,
+            "port": state.config.server.port,
+            "hidden": launch.hidden,
         }),
     }));
 }
 
+#[tauri::command]
+async fn get_settings(
+    state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
+) -> Result<serde_json::Value, Error> {
+    let state = state.lock().await;
+    Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": &state.config,
+    }))
+}
+
+/**
+ * 修改设置并写入 config.toml
+ * 能立即生效的设置直接应用,需要重启服务的字段在 restart_required 中返回
+ */
+#[tauri::command]
+async fn update_settings(
+    patch: serde_json::Value,
+    app: tauri::AppHandle,
+    state: tauri::State<'_, tokio::sync::Mutex<AppState>>,
+) -> Result<serde_json::Value, Error> {
+    let mut state = state.lock().await;
+    // 以文件内容为基础修改,避免把环境变量覆盖的值写入文件
+    let file_config = ClientConfig::read_file(&state.document_dir)?;
+    let (next_file_config, _) = file_config.apply_patch(&patch)?;
+    let mut next_config = next_file_config.clone();
+    next_config.apply_env_overrides(env::vars())?;
+    next_config.validate()?;
+    let changed = state.config.diff(&next_config);
+    // 先创建客户端,代理或证书有误时不保存设置
+    let http_client = if changed.iter().any(|key| key.starts_with("http.")) {
+        Some(http::build_client(&next_config.http)?)
+    } else {
+        None
+    };
+    // 注册失败时不保存设置
+    if changed.iter().any(|key| key == "startup.autostart") {
+        apply_autostart(&app, next_config.startup.autostart)?;
+    }
+    next_file_config.save(&state.document_dir)?;
+    if let Some(http_client) = http_client {
+        state.http_client = http_client;
+    }
+
+    if changed.iter().any(|key| key.starts_with("log.")) {
+        logger::set_level(&next_config.log.level);
+    }
+    if changed.iter().any(|key| key.starts_with("window.")) {
+        *app.state::<WindowSettings>().0.lock().unwrap() = next_config.window.clone();
+    }
+    let restart_required = changed
+        .iter()
+        .filter(|key| config::requires_restart(key))
+        .cloned()
+        .collect::<Vec<String>>();
+    // deno_bin、lobe_chat_dir 等在启动时根据配置确定,需要重启的字段保持原值直到重启
+    state.config = next_config.without_restart_changes(&state.config);
+    log::info!("settings changed {:?}", changed);
+    let _ = app.emit(
+        "settings_changed",
+        json!({ "keys": &changed, "restart_required": &restart_required }),
+    );
+    Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": json!({ "changed": changed, "restart_required": restart_required }),
+    }))
+}
+
+/**
+ * 升级旧版本的 config.toml 后再读取配置
+ * 升级前会备份原文件
+ */
+fn load_config_with_migrations(document_dir: &Path) -> Result<ClientConfig, Error> {
+    if let Some(outcome) = migrations::migrate_config(document_dir).map_err(ConfigError::from)? {
+        log::info!(
+            "config migrated from version {} to {}, backup at {}",
+            outcome.from,
+            outcome.to,
+            outcome.backup.display()
+        );
+    }
+    Ok(ClientConfig::load(document_dir)?)
+}
+
+#[tauri::command]
+async fn get_startup_report(
+    report: tauri::State<'_, tokio::sync::Mutex<StartupReport>>,
+) -> Result<serde_json::Value, Error> {
+    let report = report.lock().await;
+    Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": &*report,
+    }))
+}
+
+/**
+ * 重新启动客户端,用于启动失败后重试
+ * 新的进程启动时旧进程还没有退出,先结束服务并释放单实例锁
+ */
+#[tauri::command]
+fn retry_startup(app: tauri::AppHandle) {
+    app.state::<ServerProcess>().stop(&paths::default_app_dir());
+    app.state::<SingleInstance>().0.lock().unwrap().take();
+    app.restart();
+}
+
+/** 用系统文件管理器打开应用目录 */
+#[tauri::command]
+async fn open_app_dir(app: tauri::AppHandle) -> Result<serde_json::Value, Error> {
+    let document_dir = paths::default_app_dir();
+    app.shell()
+        .open(document_dir.display().to_string(), None)
+        .map_err(|err| Error::Process(err.to_string()))?;
+    return Ok(json!({
+        "code": 0,
+        "msg": "",
+        "data": serde_json::Value::Null,
+    }));
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    let pty_system = native_pty_system();
-    let pty_pair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    // 已有实例在运行时把参数交给它处理,由它显示窗口
+    let app_dir = paths::default_app_dir();
+    let instance = match fs::create_dir_all(&app_dir)
+        .map_err(Error::from)
+        .and_then(|_| instance::acquire(&app_dir, &args))
+    {
+        Ok(Instance::Primary(guard)) => guard,
+        Ok(Instance::Secondary) => {
+            println!("LobeChat client is already running");
+            return;
+        }
+        // 无法确认是否有其他实例时不启动,避免两个实例同时写入应用目录
+        Err(err) => {
+            eprintln!("failed to check running instance: {}", err);
+            exit(1);
+        }
+    };
 
-    let reader = pty_pair.master.try_clone_reader().unwrap();
-    let writer = pty_pair.master.take_writer().unwrap();
+    let mut report = StartupReport::default();
+    let (pty_pair, reader, writer) = match report.check("pty", pty::open_pty()) {
+        Some((pty_pair, reader, writer)) => (Some(pty_pair), Some(reader), Some(writer)),
+        None => (None, None, None),
+    };
 
-    let reader = Arc::new(Mutex::new(Some(io::BufReader::new(reader))));
-    let output = Arc::new(Mutex::new(String::new()));
+    let reader = Arc::new(Mutex::new(reader.map(io::BufReader::new)));
+    // 通过链接启动时等服务启动后再打开
+    let pending_deep_link = PendingDeepLink(Mutex::new(deep_link::find(&args).map(String::from)));
+    let launch = LaunchOptions::from_args(args.into_iter());
 
     let app = tauri::Builder::default()
-        .plugin(tauri_plugin_window_state::Builder::new().build())
+        // 不恢复窗口的显示状态,由 --hidden 决定是否显示
+        .plugin(
+            tauri_plugin_window_state::Builder::new()
+                .with_state_flags(WINDOW_STATE_FLAGS)
+                .build(),
+        )
+        .plugin(tauri_plugin_autostart::init(
+            MacosLauncher::LaunchAgent,
+            Some(vec!["--hidden"]),
+        ))
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_store::Builder::new().build())
         .invoke_handler(tauri::generate_handler![
             fetch_setup_config,
+            get_settings,
+            update_settings,
+            get_startup_report,
+            retry_startup,
+            open_app_dir,
             start_pty,
             resize_pty,
             write_to_pty,
             download_deno_then_enable,
             download_lobe_chat,
+            cancel_download,
+            get_setup_status,
             show_main_window,
         ])
         .setup(move |app| {
-            let document_dir = get_document_dir();
-            let target = match std::env::consts::OS {
-                "windows" => "x86_64-pc-windows-msvc",
-                "macos" => {
-                    if cfg!(target_pointer_width = "64") {
-                        "x86_64-apple-darwin"
-                    } else {
-                        "aarch64-apple-darwin"
-                    }
+            let document_dir = paths::default_app_dir();
+            logger::init("info");
+            let app_dir_ready = report
+                .check(
+                    "app_dir",
+                    fs::create_dir_all(&document_dir).map_err(Error::from),
+                )
+                .is_some();
+            // 应用目录不可用时使用默认配置启动,并在页面上提示
+            let loaded = if app_dir_ready {
+                report.check("config", load_config_with_migrations(&document_dir))
+            } else {
+                None
+            };
+            // 使用默认配置时不修改自动启动,避免误取消用户的设置
+            if let Some(config) = loaded.as_ref() {
+                if let Err(err) = apply_autostart(app.handle(), config.startup.autostart) {
+                    log::warn!("{}", err);
                 }
-                "linux" => {
-                    if cfg!(target_pointer_width = "64") {
-                        "x86_64-unknown-linux-gnu"
-                    } else {
-                        "aarch64-unknown-linux-gnu"
-                    }
+            }
+            // 开发时不注册,避免链接被关联到 target 目录中的程序
+            #[cfg(not(debug_assertions))]
+            match deep_link::register() {
+                Ok(true) => log::info!("registered {}:// links", deep_link::SCHEME),
+                Ok(false) => {}
+                Err(err) => {
+                    log::warn!("failed to register {}:// links: {}", deep_link::SCHEME, err)
                 }
-                _ => "x86_64-unknown-linux-gnu",
-            };
-            let proxy_url = "https://ghgo.xyz/";
-            // let proxy_url = "";
-            let deno_dir = format!("{}/.deno", dirs::home_dir().unwrap().display());
-            let deno_bin_dir = PathBuf::from(&deno_dir).join("bin");
-            let deno_bin_path = deno_bin_dir.join("deno");
-            let deno_bin_existing = deno_bin_path.exists();
-            let deno_version = String::from("2.1.4");
-            let deno_filename = format!("deno-{}.zip", target);
-            let deno_zip_filepath = PathBuf::from(&document_dir).join(deno_filename);
-            // let prefix = "https://static.funzm.com/assets/other";
-            let prefix = "https://github.com/denoland/deno/releases/download";
-            let deno_uri = format!("{}{}/v{}/deno-{}.zip", proxy_url, prefix, deno_version, target);
-
-            let lobe_build_dir = document_dir.join("lobe-chat_v1.36.11");
-            let lobe_chat_zip = document_dir.join("lobe-chat_v1.36.11.zip");
-
-            // let prefix = "https://static.funzm.com/assets/other";
-            let prefix = "https://github.com/ltaoo/LobeChatClient/releases/download/v1.36.11";
-            let lobe_chat_zip_url = format!("{}{}/lobe-chat_v1.36.11.zip", proxy_url, prefix);
-            let lobe_build_dir_existing = lobe_build_dir.exists();
-
-            fs::create_dir_all(&deno_dir).unwrap();
-            fs::create_dir_all(&deno_bin_dir).unwrap();
-            fs::create_dir_all(&document_dir).unwrap();
+            }
+            let config = loaded.unwrap_or_else(|| {
+                let mut config = ClientConfig::default();
+                let _ = config.apply_env_overrides(env::vars());
+                config
+            });
+            logger::set_level(&config.log.level);
+            let http_client = report
+                .check("http_client", http::build_client(&config.http))
+                .unwrap_or_default();
+            let target = paths::os_target();
+            let paths = Paths::new(&document_dir, target, &config);
+            // 不完整的安装会被清理,之后重新下载或解压
+            let deno_bin_existing =
+                install::check_deno(&document_dir, &paths.deno_bin, &config.deno.version);
+            let lobe_build_dir_existing = install::check_lobe_chat(
+                &document_dir,
+                &paths.lobe_chat_dir,
+                &config.lobe_chat.version,
+            );
+            if let Some(deno_bin_dir) = paths.deno_bin.parent() {
+                report.check(
+                    "deno_dir",
+                    fs::create_dir_all(deno_bin_dir).map_err(Error::from),
+                );
+            }
 
+            let window_config = config.window.clone();
             let state = tokio::sync::Mutex::new(AppState {
                 os_target: String::from(target),
                 document_dir: document_dir,
-                deno_zip_url: deno_uri,
-                deno_bin: deno_bin_path,
-                deno_version: deno_version,
+                deno_bin: paths.deno_bin,
                 deno_existing: deno_bin_existing,
-                downloading_deno_zip: deno_zip_filepath,
-                lobe_chat_zip_url,
                 lobe_chat_existing: lobe_build_dir_existing,
-                lobe_chat_dir: lobe_build_dir,
-                downloading_lobe_chat_zip: lobe_chat_zip,
-                is_downloading_deno: false,
-                is_downloading_lobe_chat: false,
+                lobe_chat_dir: paths.lobe_chat_dir,
                 pty_existing: false,
-                pty_pair: tauri::async_runtime::Mutex::new(pty_pair),
-                writer: tauri::async_runtime::Mutex::new(writer),
+                pty_pair: pty_pair.map(tauri::async_runtime::Mutex::new),
+                writer: writer.map(tauri::async_runtime::Mutex::new),
+                config,
+                http_client,
             });
             app.manage(state);
+            app.manage(WindowSettings(Mutex::new(window_config)));
+            app.manage(tokio::sync::Mutex::new(report));
+            app.manage(DownloadManager::default());
+            app.manage(SetupStatus::default());
+            app.manage(ServerProcess::default());
+            #[cfg(not(target_os = "macos"))]
+            if let Err(err) = create_tray(app) {
+                log::warn!("failed to create tray icon: {}", err);
+            }
+            app.manage(Arc::new(TauriEvents::new(app.handle().clone())));
+            if !launch.hidden {
+                if let Some(window) = app.get_webview_window("setup") {
+                    let _ = window.show();
+                }
+            }
+            app.manage(launch);
+            app.manage(pending_deep_link);
+            let app_handle = app.handle().clone();
+            let lock = instance.listen(move |args| handle_forwarded_args(&app_handle, args));
+            app.manage(SingleInstance(Mutex::new(Some(lock))));
             return Ok(());
         })
-        .on_page_load(move |window, _| {
+        .on_page_load(move |window, payload| {
             let window = window.clone();
+            if window.label() == "main" && payload.event() == PageLoadEvent::Finished {
+                open_pending_deep_link(&window);
+            }
+            let report = window.state::<tokio::sync::Mutex<StartupReport>>();
+            if let Ok(report) = report.try_lock() {
+                if !report.is_ok() {
+                    let _ = window.emit("startup_failed", &*report);
+                }
+            }
             let reader = reader.clone();
-            let output = output.clone();
+            let events = window.state::<Arc<TauriEvents>>().inner().clone();
             thread::spawn(move || {
                 let reader = reader.lock().unwrap().take();
                 if let Some(mut reader) = reader {
-                    loop {
-                        sleep(Duration::from_millis(1));
-                        let data = reader.fill_buf().unwrap().to_vec();
-                        reader.consume(data.len());
-                        if data.len() > 0 {
-                            output
-                                .lock()
-                                .unwrap()
-                                .push_str(&String::from_utf8_lossy(&data));
-                            window.emit("term_data", data).unwrap();
-                        }
-                    }
+                    pty::forward_output(&mut reader, &*events);
                 }
             });
         })
         .build(tauri::generate_context!())
         .expect("error while running tauri application");
 
-    app.run(|_app_handle, _event| {
-        match &_event {
-            RunEvent::WindowEvent {
-                event: tauri::WindowEvent::CloseRequested { api, .. },
-                label,
-                ..
-            } => {
-                async {
-                    // println!("closing window...");
-                    let state = _app_handle.state::<tokio::sync::Mutex<AppState>>();
-                    let state = state.lock().await;
-                    if state.is_downloading_deno == true {
-                        // let file = fs::File::open(&deno_zip_filepath).unwrap();
-                        fs::remove_file(&state.deno_bin).expect("Failed to delete ZIP file");
-                    }
-                    if state.is_downloading_lobe_chat == true {
-                        // let file = fs::File::open(&deno_zip_filepath).unwrap();
-                        fs::remove_file(&state.downloading_lobe_chat_zip)
-                            .expect("Failed to delete ZIP file");
-                    }
-                    // run the window destroy manually just for fun :)
-                    // usually you'd show a dialog here to ask for confirmation or whatever
-                    // let app_state = _app_handle.emit("window-close", {});
-                    // _app_handle
-                    //     .get_webview_window(label)
-                    //     .unwrap()
-                    //     .destroy()
-                    //     .unwrap();
-                    return ();
+    app.run(|app_handle, event| {
+        // macOS 点击 Dock 图标时显示后台启动的窗口
+        #[cfg(target_os = "macos")]
+        if let RunEvent::Reopen { .. } = &event {
+            show_windows(app_handle);
+        }
+        // macOS 通过系统事件而不是启动参数传递链接
+        #[cfg(target_os = "macos")]
+        if let RunEvent::Opened { urls } = &event {
+            for url in urls
+                .iter()
+                .filter(|url| deep_link::is_deep_link(url.as_str()))
+            {
+                open_deep_link(app_handle, url.as_str());
+            }
+        }
+        if let RunEvent::WindowEvent {
+            label,
+            event: tauri::WindowEvent::CloseRequested { api, .. },
+            ..
+        } = &event
+        {
+            // 关闭主窗口时只隐藏,服务继续运行,再次打开客户端时显示
+            let hide_on_close = app_handle
+                .state::<WindowSettings>()
+                .0
+                .lock()
+                .unwrap()
+                .hide_on_close;
+            if label == "main" && hide_on_close && can_hide_main_window(app_handle) {
+                api.prevent_close();
+                if let Err(err) = app_handle.save_window_state(WINDOW_STATE_FLAGS) {
+                    log::warn!("failed to save window state: {}", err);
+                }
+                if let Some(window) = app_handle.get_webview_window("main") {
+                    let _ = window.hide();
                 }
             }
-            _ => {
-                return ();
+        }
+        // 退出时取消正在进行的下载,根据设置决定是否保留已下载的部分
+        // 隐藏窗口时服务和下载都继续运行
+        if let RunEvent::ExitRequested { .. } | RunEvent::Exit = &event {
+            let state = app_handle.state::<tokio::sync::Mutex<AppState>>();
+            let state = state.try_lock().ok();
+            let keep_partial = state
+                .as_ref()
+                .map(|state| state.config.download.keep_partial)
+                .unwrap_or(true);
+            let cancelled = app_handle
+                .state::<DownloadManager>()
+                .cancel_all(keep_partial);
+            // 进程退出前下载任务来不及清理,在这里删除 .part 文件
+            if let Some(state) = state.filter(|_| !keep_partial) {
+                let paths = Paths::new(&state.document_dir, &state.os_target, &state.config);
+                for artifact in cancelled {
+                    let _ = fs::remove_file(download::partial_path(paths.archive(artifact)));
+                }
             }
-        };
+            app_handle
+                .state::<ServerProcess>()
+                .stop(&paths::default_app_dir());
+        }
     });
 }
@@ -0,0 +1,48 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/** 输出到控制台的日志,级别可以在运行时修改 */
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Error | Level::Warn => {
+                eprintln!(
+                    "[{}][{}] {}",
+                    record.level(),
+                    record.target(),
+                    record.args()
+                )
+            }
+            _ => println!(
+                "[{}][{}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+pub fn init(level: &str) {
+    let _ = log::set_logger(&LOGGER);
+    set_level(level);
+}
+
+/** 修改日志级别,无法识别的级别会被忽略 */
+pub fn set_level(level: &str) {
+    if let Ok(filter) = level.parse::<LevelFilter>() {
+        log::set_max_level(filter);
+    }
+}
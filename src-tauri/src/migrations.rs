@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::config::{write_atomic, CONFIG_FILENAME, CONFIG_SCHEMA_VERSION};
+
+/** 记录版本号的字段 */
+const VERSION_KEY: &str = "schema_version";
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error("failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{path} is corrupt: {message}")]
+    Corrupt { path: PathBuf, message: String },
+    #[error("{path} has schema version {found}, but this client only supports up to {supported}, please upgrade the client")]
+    TooNew {
+        path: PathBuf,
+        found: u32,
+        supported: u32,
+    },
+    #[error("failed to migrate {path} from version {from}: {message}")]
+    Failed {
+        path: PathBuf,
+        from: u32,
+        message: String,
+    },
+}
+
+/**
+ * 单个迁移步骤,将 from 版本的内容升级到 from + 1
+ * 内容统一转换为 JSON 处理,toml 文件在读写时转换
+ */
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut serde_json::Map<String, Value>) -> Result<(), String>,
+}
+
+/** config.toml 的迁移,按版本顺序排列 */
+pub const CONFIG_MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    description: "adopt config files written before schema_version existed",
+    apply: |_| Ok(()),
+}];
+
+/** 迁移结果,记录升级前后的版本以及备份文件 */
+#[derive(Debug)]
+pub struct MigrationOutcome {
+    pub from: u32,
+    pub to: u32,
+    pub backup: PathBuf,
+}
+
+/** 启动时升级应用目录下的 config.toml,文件不存在时跳过 */
+pub fn migrate_config(app_dir: &Path) -> Result<Option<MigrationOutcome>, MigrationError> {
+    let path = app_dir.join(CONFIG_FILENAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = read(&path)?;
+    let table = toml::from_str::<toml::Table>(&content).map_err(|err| MigrationError::Corrupt {
+        path: path.clone(),
+        message: err.message().to_string(),
+    })?;
+    let mut doc = match serde_json::to_value(table) {
+        Ok(Value::Object(doc)) => doc,
+        _ => {
+            return Err(MigrationError::Corrupt {
+                path,
+                message: String::from("not a table"),
+            })
+        }
+    };
+    let outcome = run(&path, &mut doc, CONFIG_MIGRATIONS, CONFIG_SCHEMA_VERSION)?;
+    if let Some(outcome) = outcome.as_ref() {
+        let table: toml::Table =
+            serde_json::from_value(Value::Object(doc)).map_err(|err| MigrationError::Failed {
+                path: path.clone(),
+                from: outcome.from,
+                message: err.to_string(),
+            })?;
+        let content = toml::to_string_pretty(&table).map_err(|err| MigrationError::Failed {
+            path: path.clone(),
+            from: outcome.from,
+            message: err.to_string(),
+        })?;
+        write_atomic(&path, content.as_bytes()).map_err(|source| MigrationError::Io {
+            path: path.clone(),
+            source,
+        })?;
+    }
+    Ok(outcome)
+}
+
+/**
+ * 依次执行迁移,文件已是最新版本时返回 None
+ * 执行前将原文件备份为 {filename}.v{version}.bak
+ */
+fn run(
+    path: &Path,
+    doc: &mut serde_json::Map<String, Value>,
+    migrations: &[Migration],
+    latest: u32,
+) -> Result<Option<MigrationOutcome>, MigrationError> {
+    let version = match doc.get(VERSION_KEY) {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| MigrationError::Corrupt {
+                path: path.to_path_buf(),
+                message: format!("`{}` must be a non-negative integer", VERSION_KEY),
+            })?,
+    };
+    if version > latest {
+        return Err(MigrationError::TooNew {
+            path: path.to_path_buf(),
+            found: version,
+            supported: latest,
+        });
+    }
+    if version == latest {
+        return Ok(None);
+    }
+    let backup = backup_path(path, version);
+    fs::copy(path, &backup).map_err(|source| MigrationError::Io {
+        path: backup.clone(),
+        source,
+    })?;
+    for current in version..latest {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from == current)
+            .ok_or_else(|| MigrationError::Failed {
+                path: path.to_path_buf(),
+                from: current,
+                message: String::from("no migration registered"),
+            })?;
+        log::info!(
+            "migrating {} from version {}: {}",
+            path.display(),
+            current,
+            migration.description
+        );
+        (migration.apply)(doc).map_err(|message| MigrationError::Failed {
+            path: path.to_path_buf(),
+            from: current,
+            message,
+        })?;
+        doc.insert(String::from(VERSION_KEY), Value::from(current + 1));
+    }
+    Ok(Some(MigrationOutcome {
+        from: version,
+        to: latest,
+        backup,
+    }))
+}
+
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.v{}.bak", filename, version))
+}
+
+fn read(path: &Path) -> Result<String, MigrationError> {
+    fs::read_to_string(path).map_err(|source| MigrationError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
@@ -0,0 +1,164 @@
+use reqwest::Url;
+
+/** 主窗口中一次跳转的处理方式 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Navigation {
+    /** 在主窗口中打开 */
+    Allow,
+    /** 交给系统浏览器或邮件客户端打开 */
+    External,
+    /** 忽略 */
+    Deny,
+}
+
+/**
+ * 主窗口的跳转策略
+ * 只允许本地服务和白名单中的地址,其他 http(s) 地址用系统浏览器打开,避免主窗口离开 LobeChat
+ */
+#[derive(Debug, Clone)]
+pub struct NavigationPolicy {
+    server: Url,
+    allowlist: Vec<Url>,
+}
+
+impl NavigationPolicy {
+    /** allowlist 中无法解析的地址会被忽略,配置校验时已经检查过 */
+    pub fn new(server: Url, allowlist: &[String]) -> Self {
+        NavigationPolicy {
+            server,
+            allowlist: allowlist
+                .iter()
+                .filter_map(|entry| Url::parse(entry).ok())
+                .collect(),
+        }
+    }
+
+    pub fn check(&self, url: &Url) -> Navigation {
+        match url.scheme() {
+            "http" | "https" => {}
+            // 页面内部生成的地址
+            "about" | "blob" | "data" => return Navigation::Allow,
+            "mailto" | "tel" => return Navigation::External,
+            _ => return Navigation::Deny,
+        }
+        if url.origin() == self.server.origin()
+            || self.allowlist.iter().any(|entry| is_allowed(entry, url))
+        {
+            return Navigation::Allow;
+        }
+        Navigation::External
+    }
+}
+
+/** 协议、域名、端口一致,并且路径在白名单地址的路径之下 */
+fn is_allowed(entry: &Url, url: &Url) -> bool {
+    if entry.origin() != url.origin() {
+        return false;
+    }
+    let prefix = entry.path();
+    let path = url.path();
+    path == prefix
+        || prefix.ends_with('/') && path.starts_with(prefix)
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{Navigation, NavigationPolicy};
+    use crate::config::ClientConfig;
+
+    fn policy(allowlist: &[&str]) -> NavigationPolicy {
+        let allowlist: Vec<String> = allowlist.iter().map(|url| url.to_string()).collect();
+        NavigationPolicy::new(Url::parse("http://localhost:6188/").unwrap(), &allowlist)
+    }
+
+    fn check(policy: &NavigationPolicy, url: &str) -> Navigation {
+        policy.check(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn keeps_local_server_in_main_window() {
+        let policy = policy(&[]);
+        assert_eq!(
+            check(&policy, "http://localhost:6188/chat?session=inbox"),
+            Navigation::Allow
+        );
+        assert_eq!(check(&policy, "about:blank"), Navigation::Allow);
+        // 端口不同时是另一个服务
+        assert_eq!(
+            check(&policy, "http://localhost:3000/"),
+            Navigation::External
+        );
+    }
+
+    #[test]
+    fn opens_other_urls_externally() {
+        let policy = policy(&[]);
+        assert_eq!(
+            check(&policy, "https://lobehub.com/docs"),
+            Navigation::External
+        );
+        assert_eq!(
+            check(&policy, "mailto:support@lobehub.com"),
+            Navigation::External
+        );
+        assert_eq!(check(&policy, "file:///etc/passwd"), Navigation::Deny);
+    }
+
+    #[test]
+    fn allows_urls_under_allowlist_paths() {
+        let policy = policy(&["https://github.com/login", "https://auth.example.com"]);
+        assert_eq!(
+            check(
+                &policy,
+                "https://github.com/login/oauth/authorize?client_id=1"
+            ),
+            Navigation::Allow
+        );
+        assert_eq!(
+            check(&policy, "https://github.com/login"),
+            Navigation::Allow
+        );
+        assert_eq!(
+            check(&policy, "https://github.com/loginx"),
+            Navigation::External
+        );
+        assert_eq!(
+            check(&policy, "https://github.com/lobehub"),
+            Navigation::External
+        );
+        assert_eq!(
+            check(&policy, "https://auth.example.com/callback"),
+            Navigation::Allow
+        );
+        assert_eq!(
+            check(&policy, "https://auth.example.com.evil.com/"),
+            Navigation::External
+        );
+    }
+
+    #[test]
+    fn validates_allowlist_in_config() {
+        let mut config = ClientConfig::default();
+        config.window.navigation_allowlist = vec![String::from("https://github.com/login")];
+        assert!(config.validate().is_ok());
+        config.window.navigation_allowlist = vec![String::from("github.com")];
+        assert!(config.validate().is_err());
+
+        let mut config = ClientConfig::default();
+        config
+            .apply_env_overrides([(
+                String::from("LOBE_CLIENT_WINDOW_NAVIGATION_ALLOWLIST"),
+                String::from("https://a.example.com, https://b.example.com/login,"),
+            )])
+            .unwrap();
+        assert_eq!(
+            config.window.navigation_allowlist,
+            vec!["https://a.example.com", "https://b.example.com/login"]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, ClientConfig};
+use crate::download::Artifact;
+use crate::extract::ArchiveKind;
+
+/**
+ * 安装用到的路径
+ * 全部由应用目录和配置推导,测试中使用临时目录即可与真实环境隔离
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    /** 应用目录,存放压缩包、配置文件和安装记录 */
+    pub app_dir: PathBuf,
+    /** deno 二进制文件路径 */
+    pub deno_bin: PathBuf,
+    /** 下载的 deno 压缩包 */
+    pub deno_archive: PathBuf,
+    /** LobeChat 打包产物文件夹 */
+    pub lobe_chat_dir: PathBuf,
+    /** 下载的 LobeChat 压缩包 */
+    pub lobe_chat_archive: PathBuf,
+}
+
+impl Paths {
+    pub fn new(app_dir: &Path, os_target: &str, config: &ClientConfig) -> Self {
+        let bundle_name = config.lobe_chat_bundle_name();
+        Paths {
+            app_dir: app_dir.to_path_buf(),
+            deno_bin: config.deno_install_dir().join("bin").join("deno"),
+            deno_archive: archive_filepath(
+                app_dir,
+                &format!("deno-{}", os_target),
+                &config.deno_zip_url(os_target),
+            ),
+            lobe_chat_dir: app_dir.join(&bundle_name),
+            lobe_chat_archive: archive_filepath(app_dir, &bundle_name, &config.lobe_chat_zip_url()),
+        }
+    }
+
+    /** 下载的压缩包 */
+    pub fn archive(&self, artifact: Artifact) -> &Path {
+        match artifact {
+            Artifact::Deno => &self.deno_archive,
+            Artifact::LobeChat => &self.lobe_chat_archive,
+        }
+    }
+}
+
+/**
+ * 默认的应用目录 ~/.lobe_chat_client
+ * 可以通过 LOBE_CLIENT_APP_DIR 环境变量修改
+ */
+pub fn default_app_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(format!("{}APP_DIR", config::ENV_PREFIX)) {
+        return PathBuf::from(dir);
+    }
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".lobe_chat_client")
+}
+
+/** deno 压缩包对应的平台 */
+pub fn os_target() -> &'static str {
+    match env::consts::OS {
+        "windows" => "x86_64-pc-windows-msvc",
+        "macos" => {
+            if cfg!(target_pointer_width = "64") {
+                "x86_64-apple-darwin"
+            } else {
+                "aarch64-apple-darwin"
+            }
+        }
+        "linux" => {
+            if cfg!(target_pointer_width = "64") {
+                "x86_64-unknown-linux-gnu"
+            } else {
+                "aarch64-unknown-linux-gnu"
+            }
+        }
+        _ => "x86_64-unknown-linux-gnu",
+    }
+}
+
+/** 下载的压缩包路径,后缀只是为了方便查看,解压时根据文件内容判断格式 */
+fn archive_filepath(dir: &Path, name: &str, url: &str) -> PathBuf {
+    dir.join(format!("{}{}", name, ArchiveKind::suffix_of(url)))
+}
@@ -0,0 +1,54 @@
+use std::io::{self, BufRead};
+use std::thread::sleep;
+use std::time::Duration;
+
+use portable_pty::{native_pty_system, PtyPair, PtySize};
+
+use crate::error::Error;
+use crate::events::{AppEvent, EventSink};
+
+/** 创建 pty,返回 pty 以及读写端 */
+pub fn open_pty() -> Result<(PtyPair, Box<dyn io::Read + Send>, Box<dyn io::Write + Send>), Error> {
+    let pty_system = native_pty_system();
+    let pty_pair = pty_system
+        .openpty(PtySize {
+            rows: 24,
+            cols: 80,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|err| Error::Pty(err.to_string()))?;
+    let reader = pty_pair
+        .master
+        .try_clone_reader()
+        .map_err(|err| Error::Pty(err.to_string()))?;
+    let writer = pty_pair
+        .master
+        .take_writer()
+        .map_err(|err| Error::Pty(err.to_string()))?;
+    Ok((pty_pair, reader, writer))
+}
+
+/**
+ * 持续读取 pty 或服务进程的输出并通过 TermData 发出
+ * 读取失败时发出 PtyError 后返回,输出结束时直接返回
+ */
+pub fn forward_output(reader: &mut impl BufRead, events: &dyn EventSink) {
+    loop {
+        sleep(Duration::from_millis(1));
+        let data = match reader.fill_buf() {
+            Ok(data) => data.to_vec(),
+            Err(err) => {
+                let err = Error::Pty(err.to_string());
+                log::error!("{}", err);
+                events.emit(AppEvent::PtyError(err));
+                return;
+            }
+        };
+        if data.is_empty() {
+            return;
+        }
+        reader.consume(data.len());
+        events.emit(AppEvent::TermData(data));
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{write_atomic, ConfigError};
+use crate::error::Error;
+use crate::install::LOBE_CHAT_ENTRY;
+
+/** 记录服务进程 id 的文件,位于应用目录下 */
+const PID_FILENAME: &str = "server.pid";
+
+/** 启动 LobeChat 服务的命令,窗口和命令行的 serve 共用 */
+pub fn command(deno_bin: &Path, lobe_chat_dir: &Path, port: u16) -> Command {
+    let mut command = Command::new(deno_bin);
+    command
+        .args(["run", "--allow-all", LOBE_CHAT_ENTRY])
+        .current_dir(lobe_chat_dir)
+        .env("PORT", port.to_string());
+    command
+}
+
+/** 服务的访问地址 */
+pub fn url(port: u16) -> String {
+    format!("http://localhost:{}", port)
+}
+
+pub fn pid_path(app_dir: &Path) -> PathBuf {
+    app_dir.join(PID_FILENAME)
+}
+
+pub fn write_pid(app_dir: &Path, pid: u32) -> Result<(), Error> {
+    write_atomic(&pid_path(app_dir), pid.to_string().as_bytes())?;
+    Ok(())
+}
+
+pub fn remove_pid(app_dir: &Path) {
+    let _ = fs::remove_file(pid_path(app_dir));
+}
+
+/** 正在运行的服务进程,进程已退出时清理残留的 pid 文件 */
+pub fn running_pid(app_dir: &Path) -> Option<u32> {
+    let pid = fs::read_to_string(pid_path(app_dir))
+        .ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+    if is_alive(pid) {
+        return Some(pid);
+    }
+    remove_pid(app_dir);
+    None
+}
+
+fn is_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+    #[cfg(windows)]
+    {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+            .unwrap_or(false)
+    }
+}
+
+/** 结束服务进程 */
+pub fn stop(pid: u32) -> Result<(), Error> {
+    #[cfg(unix)]
+    let output = Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .output();
+    #[cfg(windows)]
+    let output = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/T", "/F"])
+        .output();
+    let output = output.map_err(|err| Error::Process(err.to_string()))?;
+    if !output.status.success() {
+        return Err(Error::Process(format!(
+            "failed to stop process {}: {}",
+            pid,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/**
+ * 读取环境变量文件,格式与 systemd 的 EnvironmentFile 相同
+ * 每行一个 KEY=VALUE,忽略空行和 # 开头的注释,值两边的引号会被去掉
+ */
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, Error> {
+    let content = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut vars = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| ConfigError::Invalid {
+            key: String::from("server.env_file"),
+            message: format!("{}:{}: expected KEY=VALUE", path.display(), i + 1),
+        })?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| {
+                value
+                    .strip_prefix(*quote)
+                    .and_then(|value| value.strip_suffix(*quote))
+            })
+            .unwrap_or(value);
+        vars.push((key.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
@@ -0,0 +1,355 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{self, write_atomic, ClientConfig};
+use crate::error::Error;
+use crate::install::LOBE_CHAT_ENTRY;
+use crate::paths::Paths;
+
+/** systemd 单元和 autostart 文件的名称 */
+const SERVICE_NAME: &str = "lobe-chat-server";
+
+/** 登录时启动服务的方式 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceKind {
+    /** systemd --user 单元 */
+    Systemd,
+    /** 没有 systemd 用户实例时使用 XDG autostart */
+    XdgAutostart,
+}
+
+impl ServiceKind {
+    /** 生成的文件路径 */
+    pub fn path(&self) -> Result<PathBuf, Error> {
+        let config_dir =
+            dirs::config_dir().ok_or_else(|| Error::NotFound(String::from("config directory")))?;
+        Ok(match self {
+            ServiceKind::Systemd => config_dir
+                .join("systemd")
+                .join("user")
+                .join(format!("{}.service", SERVICE_NAME)),
+            ServiceKind::XdgAutostart => config_dir
+                .join("autostart")
+                .join(format!("{}.desktop", SERVICE_NAME)),
+        })
+    }
+}
+
+/** 已经生成的服务文件,两种都存在时优先 systemd */
+pub fn installed() -> Option<ServiceKind> {
+    [ServiceKind::Systemd, ServiceKind::XdgAutostart]
+        .into_iter()
+        .find(|kind| kind.path().map(|path| path.is_file()).unwrap_or(false))
+}
+
+/**
+ * 生成并启用登录时运行的服务,命令为 `lobe_chat serve`
+ * 优先使用 systemd --user,不可用或 prefer_xdg 为 true 时写入 XDG autostart 文件
+ */
+pub fn install(
+    paths: &Paths,
+    config: &ClientConfig,
+    prefer_xdg: bool,
+) -> Result<ServiceKind, Error> {
+    if !cfg!(target_os = "linux") {
+        return Err(Error::Process(String::from(
+            "login service is only supported on Linux",
+        )));
+    }
+    let exe = executable()?;
+    if !prefer_xdg && systemd_available() {
+        let path = ServiceKind::Systemd.path()?;
+        write_file(&path, &render_unit(&exe, paths, config))?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &format!("{}.service", SERVICE_NAME)])?;
+        return Ok(ServiceKind::Systemd);
+    }
+    let path = ServiceKind::XdgAutostart.path()?;
+    write_file(&path, &render_desktop_entry(&exe, paths, config))?;
+    Ok(ServiceKind::XdgAutostart)
+}
+
+/** 版本或配置变化后重新生成已有的服务文件,不会重启服务 */
+pub fn refresh(paths: &Paths, config: &ClientConfig) -> Result<Option<ServiceKind>, Error> {
+    let kind = match installed() {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+    let exe = executable()?;
+    match kind {
+        ServiceKind::Systemd => {
+            write_file(&kind.path()?, &render_unit(&exe, paths, config))?;
+            systemctl(&["daemon-reload"])?;
+        }
+        ServiceKind::XdgAutostart => {
+            write_file(&kind.path()?, &render_desktop_entry(&exe, paths, config))?;
+        }
+    }
+    Ok(Some(kind))
+}
+
+/** 停用并删除服务文件,返回删除的文件 */
+pub fn uninstall() -> Result<Vec<PathBuf>, Error> {
+    let mut removed = Vec::new();
+    let unit = ServiceKind::Systemd.path()?;
+    if unit.is_file() {
+        if systemd_available() {
+            systemctl(&["disable", "--now", &format!("{}.service", SERVICE_NAME)])?;
+        }
+        fs::remove_file(&unit)?;
+        if systemd_available() {
+            systemctl(&["daemon-reload"])?;
+        }
+        removed.push(unit);
+    }
+    let desktop = ServiceKind::XdgAutostart.path()?;
+    if desktop.is_file() {
+        fs::remove_file(&desktop)?;
+        removed.push(desktop);
+    }
+    Ok(removed)
+}
+
+/** 生成 systemd 单元,deno 或 LobeChat 缺失时不会启动 */
+pub fn render_unit(exe: &Path, paths: &Paths, config: &ClientConfig) -> String {
+    let mut unit = String::from("[Unit]\nDescription=LobeChat server\n");
+    unit.push_str("Wants=network-online.target\nAfter=network-online.target\n");
+    unit.push_str(&format!(
+        "ConditionPathExists={}\n",
+        unit_escape(&paths.deno_bin.display().to_string())
+    ));
+    unit.push_str(&format!(
+        "ConditionPathExists={}\n",
+        unit_escape(
+            &paths
+                .lobe_chat_dir
+                .join(LOBE_CHAT_ENTRY)
+                .display()
+                .to_string()
+        )
+    ));
+    unit.push_str("\n[Service]\nType=simple\n");
+    unit.push_str(&format!(
+        "Environment={}\n",
+        unit_quote(&format!(
+            "{}APP_DIR={}",
+            config::ENV_PREFIX,
+            paths.app_dir.display()
+        ))
+    ));
+    if let Some(env_file) = config.server.env_file.as_ref() {
+        unit.push_str(&format!(
+            "EnvironmentFile={}\n",
+            unit_escape(&env_file.display().to_string())
+        ));
+    }
+    unit.push_str(&format!(
+        "ExecStart={} serve --port {}\n",
+        // ExecStart 中的 $ 会被当作环境变量展开
+        unit_quote(&exe.display().to_string()).replace('$', "$$"),
+        config.server.port
+    ));
+    unit.push_str("Restart=on-failure\nRestartSec=5\n");
+    unit.push_str("\n[Install]\nWantedBy=default.target\n");
+    unit
+}
+
+/**
+ * 生成 XDG autostart 文件,桌面环境登录后执行
+ * autostart 不支持 EnvironmentFile,通过环境变量把文件交给 serve 读取
+ */
+pub fn render_desktop_entry(exe: &Path, paths: &Paths, config: &ClientConfig) -> String {
+    let mut args = vec![
+        String::from("env"),
+        format!("{}APP_DIR={}", config::ENV_PREFIX, paths.app_dir.display()),
+    ];
+    if let Some(env_file) = config.server.env_file.as_ref() {
+        args.push(format!(
+            "{}SERVER_ENV_FILE={}",
+            config::ENV_PREFIX,
+            env_file.display()
+        ));
+    }
+    args.extend([
+        exe.display().to_string(),
+        String::from("serve"),
+        String::from("--port"),
+        config.server.port.to_string(),
+    ]);
+    let exec = args
+        .iter()
+        .map(|arg| desktop_quote(arg))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "[Desktop Entry]\nType=Application\nName=LobeChat Server\nExec={}\nTerminal=false\nNoDisplay=true\nX-GNOME-Autostart-enabled=true\n",
+        exec
+    )
+}
+
+/** 当前程序的路径,AppImage 运行时使用 AppImage 文件本身 */
+pub(crate) fn executable() -> Result<PathBuf, Error> {
+    if let Some(appimage) = env::var_os("APPIMAGE") {
+        return Ok(PathBuf::from(appimage));
+    }
+    Ok(env::current_exe()?)
+}
+
+pub(crate) fn write_file(path: &Path, content: &str) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(path, content.as_bytes())?;
+    Ok(())
+}
+
+/** 是否可以连接到 systemd 的用户实例 */
+fn systemd_available() -> bool {
+    Command::new("systemctl")
+        .args(["--user", "show-environment"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+fn systemctl(args: &[&str]) -> Result<(), Error> {
+    let output = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .output()
+        .map_err(|err| Error::Process(format!("failed to run systemctl: {}", err)))?;
+    if !output.status.success() {
+        return Err(Error::Process(format!(
+            "`systemctl --user {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/** systemd 单元中的值,% 需要转义 */
+fn unit_escape(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/** systemd 单元中用引号包起来的参数 */
+fn unit_quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        unit_escape(value)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    )
+}
+
+/** desktop 文件 Exec 中的参数,包含特殊字符时用引号包起来 */
+pub(crate) fn desktop_quote(value: &str) -> String {
+    let value = value.replace('%', "%%");
+    let reserved = [
+        ' ', '\t', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')', '`',
+    ];
+    if !value.contains(reserved) {
+        return value;
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    // desktop 文件的值中反斜杠本身还需要转义一次
+    quoted.replace('\\', "\\\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{render_desktop_entry, render_unit};
+    use crate::config::ClientConfig;
+    use crate::paths::Paths;
+
+    fn config() -> ClientConfig {
+        let mut config = ClientConfig::default();
+        config
+            .apply_env_overrides([
+                (
+                    String::from("LOBE_CLIENT_SERVER_ENV_FILE"),
+                    String::from("/home/me/lobe chat.env"),
+                ),
+                (
+                    String::from("LOBE_CLIENT_SERVER_PORT"),
+                    String::from("3210"),
+                ),
+            ])
+            .unwrap();
+        config
+    }
+
+    fn paths(config: &ClientConfig) -> Paths {
+        Paths::new(
+            Path::new("/home/me/.lobe_chat_client"),
+            "x86_64-unknown-linux-gnu",
+            config,
+        )
+    }
+
+    #[test]
+    fn env_file_can_be_set_from_env() {
+        assert_eq!(
+            config().server.env_file,
+            Some(PathBuf::from("/home/me/lobe chat.env"))
+        );
+    }
+
+    #[test]
+    fn unit_uses_configured_paths_port_and_env_file() {
+        let config = config();
+        let unit = render_unit(
+            Path::new("/opt/Lobe Chat/lobe_chat"),
+            &paths(&config),
+            &config,
+        );
+        let lines: Vec<&str> = unit.lines().collect();
+        assert!(lines.contains(&"EnvironmentFile=/home/me/lobe chat.env"));
+        assert!(lines.contains(&"Environment=\"LOBE_CLIENT_APP_DIR=/home/me/.lobe_chat_client\""));
+        assert!(lines.contains(&"ExecStart=\"/opt/Lobe Chat/lobe_chat\" serve --port 3210"));
+        let entry = paths(&config).lobe_chat_dir.join("server.cjs");
+        assert!(lines.contains(&format!("ConditionPathExists={}", entry.display()).as_str()));
+    }
+
+    #[test]
+    fn unit_escapes_specifiers_and_variables() {
+        let config = ClientConfig::default();
+        let unit = render_unit(
+            Path::new("/opt/100%/$HOME/lobe_chat"),
+            &paths(&config),
+            &config,
+        );
+        assert!(unit.contains("ExecStart=\"/opt/100%%/$$HOME/lobe_chat\" serve --port 6188"));
+        assert!(!unit.contains("EnvironmentFile="));
+    }
+
+    #[test]
+    fn desktop_entry_passes_env_file_and_quotes_arguments() {
+        let config = config();
+        let entry = render_desktop_entry(
+            Path::new("/opt/Lobe Chat/lobe_chat"),
+            &paths(&config),
+            &config,
+        );
+        assert!(entry.contains(
+            "Exec=env LOBE_CLIENT_APP_DIR=/home/me/.lobe_chat_client \"LOBE_CLIENT_SERVER_ENV_FILE=/home/me/lobe chat.env\" \"/opt/Lobe Chat/lobe_chat\" serve --port 3210"
+        ), "{}", entry);
+        let config = ClientConfig::default();
+        let entry = render_desktop_entry(Path::new("/usr/bin/lobe_chat"), &paths(&config), &config);
+        assert!(entry.contains(
+            "Exec=env LOBE_CLIENT_APP_DIR=/home/me/.lobe_chat_client /usr/bin/lobe_chat serve --port 6188"
+        ), "{}", entry);
+    }
+}
@@ -0,0 +1,452 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::stream::StreamExt;
+
+use crate::config::ClientConfig;
+use crate::disk;
+use crate::download::{
+    self, Artifact, CancelToken, DownloadProgress, ProgressTracker, StallDetector,
+};
+use crate::error::Error;
+use crate::events::{AppEvent, EventSink};
+use crate::extract::{self, ArchiveKind, ExtractProgress};
+use crate::install::{self, InstallManifest};
+use crate::paths::Paths;
+use crate::setup_phase::SetupPhase;
+
+/** 需要下载的文件 */
+#[derive(Debug, Clone)]
+pub struct ArtifactSource {
+    pub url: String,
+    pub version: String,
+    /** 期望的 sha256,没有配置时不校验 */
+    pub sha256: Option<String>,
+}
+
+/**
+ * 下载、解压 deno 和 LobeChat 需要的信息
+ * 不依赖 tauri,窗口、命令行和测试共用
+ */
+#[derive(Clone)]
+pub struct SetupContext {
+    pub paths: Paths,
+    pub config: ClientConfig,
+    pub http_client: reqwest::Client,
+    pub deno: ArtifactSource,
+    pub lobe_chat: ArtifactSource,
+    events: Arc<dyn EventSink>,
+}
+
+impl SetupContext {
+    pub fn new(
+        paths: Paths,
+        os_target: &str,
+        config: ClientConfig,
+        http_client: reqwest::Client,
+        events: Arc<dyn EventSink>,
+    ) -> Self {
+        SetupContext {
+            paths,
+            deno: ArtifactSource {
+                url: config.deno_zip_url(os_target),
+                version: config.deno.version.clone(),
+                sha256: config.deno.sha256.clone(),
+            },
+            lobe_chat: ArtifactSource {
+                url: config.lobe_chat_zip_url(),
+                version: config.lobe_chat.version.clone(),
+                sha256: config.lobe_chat.sha256.clone(),
+            },
+            config,
+            http_client,
+            events,
+        }
+    }
+
+    fn set_phase(&self, phase: SetupPhase) {
+        self.events.emit(AppEvent::Phase(phase));
+    }
+
+    /** 下载并安装 deno,已有压缩包时直接解压 */
+    pub async fn install_deno(&self, token: &CancelToken) -> Result<(), Error> {
+        let source = &self.deno;
+        let archive = &self.paths.deno_archive;
+        if !archive.exists() {
+            self.download_file(token, source, archive, |progress| {
+                SetupPhase::DownloadingRuntime {
+                    progress,
+                    url: source.url.clone(),
+                    target: archive.clone(),
+                }
+            })
+            .await?;
+        }
+        let deno_bin = self.paths.deno_bin.clone();
+        self.extract_then_install(
+            Artifact::Deno,
+            &source.version,
+            archive,
+            token,
+            move |archive, token, on_progress| {
+                install_deno_from_archive(archive, &deno_bin, token, on_progress)
+            },
+        )
+        .await
+    }
+
+    /** 下载并安装 LobeChat 打包产物,已有压缩包时直接解压 */
+    pub async fn install_lobe_chat(&self, token: &CancelToken) -> Result<(), Error> {
+        let source = &self.lobe_chat;
+        let archive = &self.paths.lobe_chat_archive;
+        if !archive.exists() {
+            self.download_file(token, source, archive, |progress| {
+                SetupPhase::DownloadingBundle {
+                    progress,
+                    url: source.url.clone(),
+                    target: archive.clone(),
+                }
+            })
+            .await?;
+        }
+        let lobe_chat_dir = self.paths.lobe_chat_dir.clone();
+        self.extract_then_install(
+            Artifact::LobeChat,
+            &source.version,
+            archive,
+            token,
+            move |archive, token, on_progress| {
+                install_lobe_chat_from_archive(archive, &lobe_chat_dir, token, on_progress)
+            },
+        )
+        .await
+    }
+
+    /** 在后台线程中解压并安装,成功后写入安装记录 */
+    async fn extract_then_install<F>(
+        &self,
+        artifact: Artifact,
+        version: &str,
+        archive: &Path,
+        token: &CancelToken,
+        install: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(
+                &Path,
+                &CancelToken,
+                &mut dyn FnMut(&ExtractProgress),
+            ) -> Result<ArchiveKind, Error>
+            + Send
+            + 'static,
+    {
+        self.set_phase(SetupPhase::Extracting {
+            file: archive.to_path_buf(),
+        });
+        let events = self.events.clone();
+        let token = token.clone();
+        let r = run_blocking({
+            let archive = archive.to_path_buf();
+            move || {
+                let mut on_progress = |progress: &ExtractProgress| {
+                    events.emit(AppEvent::ExtractProgress {
+                        file: archive.clone(),
+                        progress: progress.clone(),
+                    })
+                };
+                let kind = install(&archive, &token, &mut on_progress)?;
+                Ok((kind, install::sha256_file(&archive)?))
+            }
+        })
+        .await;
+        let (kind, sha256) = match r {
+            Ok(r) => r,
+            Err(err) => {
+                // 压缩包损坏时重新下载,空间不足时保留,释放空间后可以直接解压
+                if matches!(err, Error::Extract { .. } | Error::Install { .. }) {
+                    let _ = fs::remove_file(archive);
+                }
+                return Err(err);
+            }
+        };
+        InstallManifest::new(artifact, version, Some(kind), Some(sha256))
+            .save(&self.paths.app_dir)?;
+        Ok(())
+    }
+
+    /**
+     * 下载文件到指定路径,进度变化时用 to_phase 生成新的阶段
+     * 下载中的数据写入 .part 文件,存在 .part 文件时从已下载的位置继续
+     * 遇到临时性的错误时按 download.retry 的配置重试,配置了 sha256 时校验通过才移动到指定路径
+     */
+    async fn download_file(
+        &self,
+        token: &CancelToken,
+        source: &ArtifactSource,
+        filepath: &Path,
+        to_phase: impl Fn(DownloadProgress) -> SetupPhase,
+    ) -> Result<(), Error> {
+        let url = source.url.as_str();
+        let partial_filepath = download::partial_path(filepath);
+        let retry = &self.config.download.retry;
+        let mut attempt = 0;
+        let r = loop {
+            let err = match self
+                .download_to_partial(token, url, &partial_filepath, &to_phase)
+                .await
+            {
+                Ok(()) => break Ok(()),
+                Err(err) => err,
+            };
+            if token.is_cancelled() || !err.is_transient() || attempt >= retry.max_retries {
+                break Err(err);
+            }
+            attempt += 1;
+            let delay = match download::retry_delay(retry, attempt, err.retry_after()) {
+                Some(delay) => delay,
+                None => {
+                    log::warn!(
+                        "download {} failed, server asks to retry much later: {}",
+                        url,
+                        err
+                    );
+                    break Err(err);
+                }
+            };
+            log::warn!(
+                "download {} failed, retry {} in {:?}: {}",
+                url,
+                attempt,
+                delay,
+                err
+            );
+            self.events.emit(AppEvent::Retrying {
+                url: String::from(url),
+                attempt,
+                max_retries: retry.max_retries,
+                delay,
+                reason: err,
+            });
+            tokio::select! {
+                _ = token.cancelled() => break Err(Error::Cancelled(String::from(url))),
+                _ = tokio::time::sleep(delay) => {}
+            }
+        };
+        match r {
+            Ok(()) => {
+                if let Some(expected) = source.sha256.as_ref() {
+                    verify_checksum(&partial_filepath, filepath, expected)?;
+                }
+                fs::rename(&partial_filepath, filepath)?;
+                Ok(())
+            }
+            Err(err) => {
+                if token.is_cancelled() && token.keep_partial() {
+                    log::info!("keep partial download {}", partial_filepath.display());
+                } else {
+                    let _ = fs::remove_file(&partial_filepath);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn download_to_partial(
+        &self,
+        token: &CancelToken,
+        url: &str,
+        partial_filepath: &Path,
+        to_phase: &impl Fn(DownloadProgress) -> SetupPhase,
+    ) -> Result<(), Error> {
+        let http_error = |source: reqwest::Error| Error::Http {
+            url: String::from(url),
+            source,
+        };
+        let http_config = &self.config.http;
+        let existing_size = fs::metadata(partial_filepath)
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        let mut request = self.http_client.get(url);
+        if existing_size > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing_size));
+        }
+        let response = tokio::select! {
+            _ = token.cancelled() => return Err(Error::Cancelled(String::from(url))),
+            response = request.send() => response.map_err(http_error)?,
+        };
+        if !response.status().is_success() {
+            // Retry-After 也可能是 HTTP 日期,这种情况使用默认的退避时间
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            return Err(Error::Status {
+                url: String::from(url),
+                status: response.status().as_u16(),
+                retry_after,
+            });
+        }
+        // 服务端支持 Range 时追加写入,否则重新下载
+        let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let downloaded: u64 = if resumed { existing_size } else { 0 };
+        // 分块传输的响应没有 Content-Length,此时只展示已下载的大小
+        let total_size = response.content_length().map(|len| len + downloaded);
+        // 还需下载的部分加上估算的解压后大小
+        if let Some(total_size) = total_size {
+            let remaining = total_size - downloaded;
+            let extracted = total_size.saturating_mul(disk::ESTIMATED_EXTRACT_RATIO);
+            disk::ensure_space(partial_filepath, remaining.saturating_add(extracted))?;
+        }
+        let mut tracker = ProgressTracker::new(downloaded, total_size);
+        self.set_phase(to_phase(tracker.progress()));
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(partial_filepath)?;
+        let mut writer = io::BufWriter::new(file);
+        let mut stream = response.bytes_stream();
+        let mut stall = StallDetector::new(
+            Duration::from_secs(http_config.stall_timeout_secs),
+            http_config.stall_min_speed,
+        );
+        let stalled = || Error::Stalled {
+            url: String::from(url),
+            secs: http_config.stall_timeout_secs,
+            min_speed: http_config.stall_min_speed,
+        };
+        loop {
+            let chunk = tokio::select! {
+                _ = token.cancelled() => {
+                    writer.flush()?;
+                    return Err(Error::Cancelled(String::from(url)));
+                }
+                chunk = tokio::time::timeout(stall.timeout(), stream.next()) => chunk,
+            };
+            let chunk = match chunk {
+                Ok(Some(chunk)) => chunk.map_err(http_error)?,
+                Ok(None) => break,
+                Err(_) => {
+                    writer.flush()?;
+                    return Err(stalled());
+                }
+            };
+            writer.write_all(&chunk)?;
+            if stall.record(chunk.len() as u64) {
+                writer.flush()?;
+                return Err(stalled());
+            }
+            if let Some(progress) = tracker.advance(chunk.len() as u64) {
+                self.set_phase(to_phase(progress));
+            }
+        }
+        writer.flush()?;
+        self.set_phase(to_phase(tracker.finish()));
+        Ok(())
+    }
+}
+
+/** 在后台线程中执行解压等耗时操作,避免阻塞异步运行时 */
+async fn run_blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| Error::Process(err.to_string()))?
+}
+
+/**
+ * 校验下载完成的 .part 文件,不一致时删除 .part 文件和已有的压缩包,下次重新下载
+ * 报错时使用压缩包的路径,与解压失败等错误一致
+ */
+fn verify_checksum(partial_filepath: &Path, filepath: &Path, expected: &str) -> Result<(), Error> {
+    let actual = install::sha256_file(partial_filepath)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        return Ok(());
+    }
+    log::warn!(
+        "checksum mismatch for {}, expected {} but got {}",
+        filepath.display(),
+        expected,
+        actual
+    );
+    let _ = fs::remove_file(partial_filepath);
+    let _ = fs::remove_file(filepath);
+    Err(Error::Checksum {
+        path: filepath.to_path_buf(),
+        expected: expected.to_lowercase(),
+        actual,
+    })
+}
+
+/** 为 deno 添加可执行权限 */
+fn set_executable(path: &Path) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o755);
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/**
+ * 先解压到临时目录,deno 能正常运行后再移动到安装位置
+ * 解压中断时不会留下不完整的 deno,返回压缩包的格式
+ */
+fn install_deno_from_archive(
+    archive: &Path,
+    deno_bin: &Path,
+    token: &CancelToken,
+    on_progress: &mut dyn FnMut(&ExtractProgress),
+) -> Result<ArchiveKind, Error> {
+    let staging = install::staging_path(deno_bin);
+    let _ = fs::remove_dir_all(&staging);
+    let r = (|| {
+        if let Some(dir) = deno_bin.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let kind = extract::extract(archive, &staging, token, on_progress)?;
+        let filename = deno_bin
+            .file_name()
+            .ok_or_else(|| Error::NotFound(deno_bin.display().to_string()))?;
+        let staged_bin = install::find_file(&staging, filename).ok_or_else(|| Error::Install {
+            path: archive.to_path_buf(),
+            message: format!("{} not found in archive", filename.to_string_lossy()),
+        })?;
+        set_executable(&staged_bin)?;
+        install::validate_deno(&staged_bin)?;
+        fs::rename(&staged_bin, deno_bin)?;
+        Ok(kind)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    r
+}
+
+/** 先解压到临时目录,校验通过后替换安装目录,返回压缩包的格式 */
+fn install_lobe_chat_from_archive(
+    archive: &Path,
+    lobe_chat_dir: &Path,
+    token: &CancelToken,
+    on_progress: &mut dyn FnMut(&ExtractProgress),
+) -> Result<ArchiveKind, Error> {
+    let staging = install::staging_path(lobe_chat_dir);
+    let _ = fs::remove_dir_all(&staging);
+    let r = extract::extract(archive, &staging, token, on_progress).and_then(|kind| {
+        install::validate_lobe_chat(&staging)?;
+        install::commit_dir(&staging, lobe_chat_dir)?;
+        Ok(kind)
+    });
+    if r.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    r
+}
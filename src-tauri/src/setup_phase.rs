@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::download::DownloadProgress;
+
+/**
+ * 初始化 LobeChat 的阶段
+ * 保存在 SetupStatus 中,每次变化都通过 setup_phase_changed 事件通知前端
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum SetupPhase {
+    /** 检查 deno 是否存在 */
+    #[default]
+    CheckingRuntime,
+    /** 下载 deno */
+    DownloadingRuntime {
+        #[serde(flatten)]
+        progress: DownloadProgress,
+        url: String,
+        target: PathBuf,
+    },
+    /** 解压 deno 或 LobeChat 压缩包 */
+    Extracting { file: PathBuf },
+    /** 下载 LobeChat 打包产物 */
+    DownloadingBundle {
+        #[serde(flatten)]
+        progress: DownloadProgress,
+        url: String,
+        target: PathBuf,
+    },
+    /** 文件已准备好,正在启动 LobeChat 服务 */
+    StartingServer,
+    /** 服务已启动 */
+    Ready { url: String },
+    /** 失败,error 为序列化后的 Error */
+    Failed { error: serde_json::Value },
+}
+
+impl SetupPhase {
+    /** 是否正在下载 */
+    pub fn is_downloading(&self) -> bool {
+        matches!(
+            self,
+            SetupPhase::DownloadingRuntime { .. } | SetupPhase::DownloadingBundle { .. }
+        )
+    }
+}
+
+/**
+ * 当前的初始化阶段
+ * 下载任务在同步的回调中更新,所以使用 std 的 Mutex
+ * 页面刷新后可以通过 get_setup_status 重新获取
+ */
+#[derive(Debug, Default)]
+pub struct SetupStatus {
+    phase: Mutex<SetupPhase>,
+}
+
+impl SetupStatus {
+    pub fn get(&self) -> SetupPhase {
+        self.phase.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, phase: SetupPhase) {
+        *self.phase.lock().unwrap() = phase;
+    }
+}
+
+/**
+ * 从服务输出中找到 LobeChat 的访问地址
+ * 输出形如 `Local:   http://localhost:6188`,地址后可能跟着终端颜色控制符
+ */
+pub fn find_server_url(output: &str) -> Option<String> {
+    let start = output.rfind("Local:")? + "Local:".len();
+    let rest = output[start..].trim_start_matches([' ', '\t']);
+    let rest = rest.strip_prefix("\u{1b}[").map_or(rest, |r| {
+        r.trim_start_matches(|c: char| c.is_ascii_digit() || c == ';')
+            .trim_start_matches('m')
+    });
+    let url: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || ":/.-_".contains(*c))
+        .collect();
+    if url.starts_with("http") {
+        Some(url)
+    } else {
+        None
+    }
+}
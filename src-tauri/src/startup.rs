@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+use crate::error::Error;
+
+/** 启动时的一个步骤失败 */
+#[derive(Debug, Serialize)]
+pub struct StartupFailure {
+    /** 失败的步骤,如 config、pty */
+    pub step: &'static str,
+    pub error: Error,
+}
+
+/**
+ * 启动过程的结果
+ * setup 中的每个步骤失败后都记录在这里而不是直接 panic,启动窗口会展示这些错误
+ */
+#[derive(Debug, Default, Serialize)]
+pub struct StartupReport {
+    pub failures: Vec<StartupFailure>,
+}
+
+impl StartupReport {
+    /** 记录步骤的结果,失败时返回 None */
+    pub fn check<T>(&mut self, step: &'static str, result: Result<T, Error>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                log::error!("startup step `{}` failed: {}", step, error);
+                self.failures.push(StartupFailure { step, error });
+                None
+            }
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
@@ -0,0 +1,402 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use lobe_chat_lib::config::{ClientConfig, RetryConfig};
+use lobe_chat_lib::events::MemorySink;
+use lobe_chat_lib::extract::ArchiveKind;
+use lobe_chat_lib::paths::Paths;
+use lobe_chat_lib::setup::SetupContext;
+
+pub const OS_TARGET: &str = "x86_64-unknown-linux-gnu";
+pub const DENO_VERSION: &str = "2.1.4";
+pub const SERVER_ENTRY: &[u8] = b"console.log('LobeChat');\n";
+
+/** 测试用的临时目录,结束后删除 */
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "lobe_chat_test_{}_{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/** 文件服务器返回的一个文件 */
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub body: Vec<u8>,
+    pub status: u16,
+    /** 为 false 时使用分块传输,不返回 Content-Length */
+    pub content_length: bool,
+    /** 是否支持 Range 请求 */
+    pub ranges: bool,
+    /** 前几次请求只返回一半的数据后断开连接 */
+    pub truncate_times: usize,
+    /** 出错时返回的 Retry-After 秒数 */
+    pub retry_after: Option<u64>,
+}
+
+impl Fixture {
+    pub fn new(body: Vec<u8>) -> Self {
+        Fixture {
+            body,
+            status: 200,
+            content_length: true,
+            ranges: true,
+            truncate_times: 0,
+            retry_after: None,
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Fixture {
+            status,
+            ..Fixture::new(Vec::new())
+        }
+    }
+
+    pub fn without_content_length(mut self) -> Self {
+        self.content_length = false;
+        self
+    }
+
+    pub fn without_ranges(mut self) -> Self {
+        self.ranges = false;
+        self
+    }
+
+    pub fn truncated(mut self, times: usize) -> Self {
+        self.truncate_times = times;
+        self
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+}
+
+/** 服务器收到的请求 */
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub path: String,
+    pub range: Option<String>,
+}
+
+/** 只监听本机地址的 HTTP 文件服务器,不需要访问网络 */
+pub struct FixtureServer {
+    addr: String,
+    fixtures: Arc<Mutex<HashMap<String, Fixture>>>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl FixtureServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let fixtures = Arc::new(Mutex::new(HashMap::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        {
+            let fixtures = fixtures.clone();
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let fixtures = fixtures.clone();
+                    let requests = requests.clone();
+                    thread::spawn(move || handle(stream, &fixtures, &requests));
+                }
+            });
+        }
+        FixtureServer {
+            addr,
+            fixtures,
+            requests,
+        }
+    }
+
+    pub fn serve(&self, path: &str, fixture: Fixture) -> String {
+        self.fixtures
+            .lock()
+            .unwrap()
+            .insert(String::from(path), fixture);
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    fixtures: &Mutex<HashMap<String, Fixture>>,
+    requests: &Mutex<Vec<Request>>,
+) {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.lines();
+    let path = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/")
+        .to_string();
+    let range = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("range"))
+        .map(|(_, value)| value.trim().to_string());
+    requests.lock().unwrap().push(Request {
+        path: path.clone(),
+        range: range.clone(),
+    });
+
+    let fixture = {
+        let mut fixtures = fixtures.lock().unwrap();
+        let fixture = fixtures.get_mut(&path).map(|fixture| {
+            let current = fixture.clone();
+            fixture.truncate_times = fixture.truncate_times.saturating_sub(1);
+            current
+        });
+        fixture.unwrap_or_else(|| Fixture::status(404))
+    };
+    if fixture.status != 200 {
+        let retry_after = fixture
+            .retry_after
+            .map(|secs| format!("Retry-After: {}\r\n", secs))
+            .unwrap_or_default();
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {} Error\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+            fixture.status, retry_after
+        );
+        return;
+    }
+
+    let start = range
+        .as_deref()
+        .filter(|_| fixture.ranges)
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+        .filter(|start| *start < fixture.body.len());
+    let mut response = match start {
+        Some(start) => format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            start,
+            fixture.body.len() - 1,
+            fixture.body.len()
+        ),
+        None => String::from("HTTP/1.1 200 OK\r\n"),
+    };
+    let body = &fixture.body[start.unwrap_or(0)..];
+    if fixture.content_length {
+        response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    } else {
+        response.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    response.push_str("Connection: close\r\n\r\n");
+    if stream.write_all(response.as_bytes()).is_err() {
+        return;
+    }
+    let body = if fixture.truncate_times > 0 {
+        &body[..body.len() / 2]
+    } else {
+        body
+    };
+    if fixture.content_length {
+        let _ = stream.write_all(body);
+    } else {
+        for chunk in body.chunks(4096) {
+            let _ = write!(stream, "{:x}\r\n", chunk.len());
+            let _ = stream.write_all(chunk);
+            let _ = stream.write_all(b"\r\n");
+        }
+        if fixture.truncate_times == 0 {
+            let _ = stream.write_all(b"0\r\n\r\n");
+        }
+    }
+    let _ = stream.flush();
+}
+
+/** 用给定的文件生成 zip,mode 为 unix 权限 */
+pub fn zip(entries: &[(&str, &[u8], u32)]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content, mode) in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(*mode);
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/** 用给定的文件生成 tar 包,再按 kind 压缩,条目名不做检查以便构造非法的路径 */
+pub fn tar(kind: ArchiveKind, entries: &[(&str, &[u8], u32)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, content, mode) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(content.len() as u64);
+        header.set_mode(*mode);
+        header.set_cksum();
+        builder.append(&header, *content).unwrap();
+    }
+    let tar = builder.into_inner().unwrap();
+    match kind {
+        ArchiveKind::TarGz => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        }
+        ArchiveKind::TarXz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 1);
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        }
+        ArchiveKind::TarZst => zstd::encode_all(Cursor::new(tar), 1).unwrap(),
+        ArchiveKind::Zip => panic!("use zip() for zip archives"),
+    }
+}
+
+/** 下载地址的后缀,与 ArchiveKind::suffix_of 对应 */
+pub fn suffix(kind: ArchiveKind) -> &'static str {
+    match kind {
+        ArchiveKind::Zip => ".zip",
+        ArchiveKind::TarGz => ".tar.gz",
+        ArchiveKind::TarXz => ".tar.xz",
+        ArchiveKind::TarZst => ".tar.zst",
+    }
+}
+
+fn lobe_chat_entries(assets: &[u8]) -> [(&str, &[u8], u32); 2] {
+    [
+        ("server.cjs", SERVER_ENTRY, 0o644),
+        ("public/assets.js", assets, 0o644),
+    ]
+}
+
+/** 只包含入口文件的 LobeChat 打包产物,内容足够大以便分多次传输 */
+pub fn lobe_chat_zip() -> Vec<u8> {
+    let assets = vec![b'a'; 256 * 1024];
+    zip(&lobe_chat_entries(&assets))
+}
+
+/** 与 lobe_chat_zip 内容相同的 tar 包 */
+pub fn lobe_chat_tar(kind: ArchiveKind) -> Vec<u8> {
+    let assets = vec![b'a'; 256 * 1024];
+    tar(kind, &lobe_chat_entries(&assets))
+}
+
+/** 用 shell 脚本代替 deno,能响应 --version */
+pub fn deno_zip() -> Vec<u8> {
+    let script = format!(
+        "#!/bin/sh\necho \"deno {} (stable, release, {})\"\n",
+        DENO_VERSION, OS_TARGET
+    );
+    zip(&[("deno", script.as_bytes(), 0o755)])
+}
+
+/** 修改入口文件的内容,使 zip 中记录的 crc32 校验失败 */
+pub fn corrupt_zip() -> Vec<u8> {
+    let mut bytes = lobe_chat_zip();
+    let offset = bytes
+        .windows(SERVER_ENTRY.len())
+        .position(|window| window == SERVER_ENTRY)
+        .unwrap();
+    bytes[offset] ^= 0xff;
+    bytes
+}
+
+/** 包含 ../ 的条目,解压时会写到输出目录之外 */
+pub fn traversal_zip() -> Vec<u8> {
+    zip(&[
+        ("server.cjs", SERVER_ENTRY, 0o644),
+        ("../escaped.txt", b"escaped", 0o644),
+    ])
+}
+
+/** 与 traversal_zip 相同的 tar 包 */
+pub fn traversal_tar(kind: ArchiveKind) -> Vec<u8> {
+    tar(
+        kind,
+        &[
+            ("server.cjs", SERVER_ENTRY, 0o644),
+            ("../escaped.txt", b"escaped", 0o644),
+        ],
+    )
+}
+
+/** 指向文件服务器的配置,重试间隔缩短到毫秒级 */
+pub fn config(dir: &Path, deno_url: &str, lobe_chat_url: &str) -> ClientConfig {
+    let mut config = ClientConfig::default();
+    config.deno.version = String::from(DENO_VERSION);
+    config.deno.url = Some(String::from(deno_url));
+    config.deno.install_dir = Some(dir.join("deno"));
+    config.lobe_chat.url = Some(String::from(lobe_chat_url));
+    config.download.retry = RetryConfig {
+        max_retries: 3,
+        initial_delay_ms: 1,
+        max_delay_ms: 10,
+    };
+    config.http.stall_timeout_secs = 5;
+    config
+}
+
+/** 创建安装到临时目录的 SetupContext,不使用系统代理 */
+pub fn context(dir: &Path, config: ClientConfig) -> (SetupContext, Arc<MemorySink>) {
+    let events = Arc::new(MemorySink::default());
+    let http_client = reqwest::Client::builder().no_proxy().build().unwrap();
+    let ctx = SetupContext::new(
+        Paths::new(dir, OS_TARGET, &config),
+        OS_TARGET,
+        config,
+        http_client,
+        events.clone(),
+    );
+    (ctx, events)
+}
@@ -0,0 +1,450 @@
+mod common;
+
+use std::fs;
+
+use lobe_chat_lib::download::{self, Artifact, CancelToken};
+use lobe_chat_lib::error::Error;
+use lobe_chat_lib::events::AppEvent;
+use lobe_chat_lib::extract::ArchiveKind;
+use lobe_chat_lib::install::{self, InstallManifest};
+use lobe_chat_lib::setup_phase::SetupPhase;
+
+use common::{Fixture, FixtureServer, TempDir};
+
+/** 下载过程中最后一次通知的进度 */
+fn last_bundle_progress(events: &[AppEvent]) -> Option<download::DownloadProgress> {
+    events.iter().rev().find_map(|event| match event {
+        AppEvent::Phase(SetupPhase::DownloadingBundle { progress, .. }) => Some(progress.clone()),
+        _ => None,
+    })
+}
+
+fn retry_count(events: &[AppEvent]) -> usize {
+    events
+        .iter()
+        .filter(|event| matches!(event, AppEvent::Retrying { .. }))
+        .count()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn installs_deno_and_lobe_chat() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let deno_url = server.serve("/deno.zip", Fixture::new(common::deno_zip()));
+    let lobe_chat_url = server.serve("/lobe-chat.zip", Fixture::new(common::lobe_chat_zip()));
+    let (ctx, events) = common::context(
+        dir.path(),
+        common::config(dir.path(), &deno_url, &lobe_chat_url),
+    );
+    let token = CancelToken::default();
+
+    ctx.install_deno(&token).await.unwrap();
+    ctx.install_lobe_chat(&token).await.unwrap();
+
+    assert_eq!(
+        install::validate_deno(&ctx.paths.deno_bin).unwrap(),
+        common::DENO_VERSION
+    );
+    assert_eq!(
+        fs::read(ctx.paths.lobe_chat_dir.join(install::LOBE_CHAT_ENTRY)).unwrap(),
+        common::SERVER_ENTRY
+    );
+    assert!(!install::staging_path(&ctx.paths.lobe_chat_dir).exists());
+    for artifact in [Artifact::Deno, Artifact::LobeChat] {
+        let manifest = InstallManifest::read(dir.path(), artifact).unwrap();
+        assert!(manifest.sha256.is_some());
+        assert_eq!(manifest.archive, Some(ArchiveKind::Zip));
+    }
+    assert!(install::check_deno(
+        dir.path(),
+        &ctx.paths.deno_bin,
+        &ctx.deno.version
+    ));
+    assert!(install::check_lobe_chat(
+        dir.path(),
+        &ctx.paths.lobe_chat_dir,
+        &ctx.lobe_chat.version
+    ));
+
+    let events = events.take();
+    let progress = last_bundle_progress(&events).unwrap();
+    assert_eq!(progress.total, Some(common::lobe_chat_zip().len() as u64));
+    assert_eq!(progress.percent, Some(100.0));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, AppEvent::Phase(SetupPhase::Extracting { .. }))));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, AppEvent::ExtractProgress { .. })));
+    assert_eq!(retry_count(&events), 0);
+}
+
+#[tokio::test]
+async fn installs_lobe_chat_from_tar_archives() {
+    for kind in [ArchiveKind::TarGz, ArchiveKind::TarXz, ArchiveKind::TarZst] {
+        let server = FixtureServer::start();
+        let dir = TempDir::new();
+        let body = common::lobe_chat_tar(kind);
+        assert_eq!(ArchiveKind::from_magic(&body), Some(kind));
+        let path = format!("/lobe-chat{}", common::suffix(kind));
+        let url = server.serve(&path, Fixture::new(body));
+        let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+        ctx.install_lobe_chat(&CancelToken::default())
+            .await
+            .unwrap();
+
+        assert!(ctx
+            .paths
+            .lobe_chat_archive
+            .to_string_lossy()
+            .ends_with(common::suffix(kind)));
+        assert_eq!(
+            ArchiveKind::detect(&ctx.paths.lobe_chat_archive).unwrap(),
+            kind
+        );
+        assert_eq!(
+            fs::read(ctx.paths.lobe_chat_dir.join(install::LOBE_CHAT_ENTRY)).unwrap(),
+            common::SERVER_ENTRY
+        );
+        assert!(ctx.paths.lobe_chat_dir.join("public/assets.js").is_file());
+        let manifest = InstallManifest::read(dir.path(), Artifact::LobeChat).unwrap();
+        assert_eq!(manifest.archive, Some(kind), "{:?}", kind);
+    }
+}
+
+#[tokio::test]
+async fn downloads_without_content_length() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve(
+        "/lobe-chat.zip",
+        Fixture::new(common::lobe_chat_zip()).without_content_length(),
+    );
+    let (ctx, events) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    ctx.install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap();
+
+    let events = events.take();
+    let first = events
+        .iter()
+        .find_map(|event| match event {
+            AppEvent::Phase(SetupPhase::DownloadingBundle { progress, .. }) => Some(progress),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(first.total, None);
+    assert_eq!(first.percent, None);
+    // 下载完成后用实际大小作为总大小
+    let last = last_bundle_progress(&events).unwrap();
+    assert_eq!(last.total, Some(common::lobe_chat_zip().len() as u64));
+    assert!(ctx
+        .paths
+        .lobe_chat_dir
+        .join(install::LOBE_CHAT_ENTRY)
+        .is_file());
+}
+
+#[tokio::test]
+async fn resumes_truncated_download_with_range() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let body = common::lobe_chat_zip();
+    let url = server.serve("/lobe-chat.zip", Fixture::new(body.clone()).truncated(1));
+    let (ctx, events) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    ctx.install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap();
+
+    let requests = server.requests("/lobe-chat.zip");
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].range, None);
+    assert_eq!(
+        requests[1].range.as_deref(),
+        Some(format!("bytes={}-", body.len() / 2).as_str())
+    );
+    assert_eq!(retry_count(&events.take()), 1);
+    assert_eq!(fs::read(&ctx.paths.lobe_chat_archive).unwrap(), body);
+    assert!(!download::partial_path(&ctx.paths.lobe_chat_archive).exists());
+}
+
+#[tokio::test]
+async fn restarts_download_when_range_is_not_supported() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let body = common::lobe_chat_zip();
+    let url = server.serve(
+        "/lobe-chat.zip",
+        Fixture::new(body.clone()).without_ranges().truncated(1),
+    );
+    let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    ctx.install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap();
+
+    let requests = server.requests("/lobe-chat.zip");
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].range.is_some());
+    // 服务端忽略 Range 返回完整内容时覆盖已下载的部分
+    assert_eq!(fs::read(&ctx.paths.lobe_chat_archive).unwrap(), body);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve(
+        "/lobe-chat.zip",
+        Fixture::new(common::lobe_chat_zip())
+            .without_ranges()
+            .truncated(usize::MAX),
+    );
+    let (ctx, events) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    assert!(err.is_transient(), "{:?}", err);
+    assert_eq!(server.requests("/lobe-chat.zip").len(), 4);
+    assert_eq!(retry_count(&events.take()), 3);
+    assert!(!ctx.paths.lobe_chat_archive.exists());
+    assert!(!download::partial_path(&ctx.paths.lobe_chat_archive).exists());
+    assert!(!ctx.paths.lobe_chat_dir.exists());
+}
+
+#[tokio::test]
+async fn gives_up_when_retry_after_is_too_long() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve(
+        "/lobe-chat.zip",
+        Fixture::status(503).retry_after(download::MAX_RETRY_AFTER.as_secs() + 1),
+    );
+    let (ctx, events) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err,
+            Error::Status {
+                status: 503,
+                retry_after: Some(_),
+                ..
+            }
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(server.requests("/lobe-chat.zip").len(), 1);
+    assert_eq!(retry_count(&events.take()), 0);
+}
+
+#[tokio::test]
+async fn does_not_retry_missing_file() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve("/lobe-chat.zip", Fixture::status(404));
+    let (ctx, events) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(err, Error::Status { status: 404, .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(server.requests("/lobe-chat.zip").len(), 1);
+    assert_eq!(retry_count(&events.take()), 0);
+}
+
+#[tokio::test]
+async fn rejects_archive_with_corrupt_entry_crc() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve("/lobe-chat.zip", Fixture::new(common::corrupt_zip()));
+    let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Extract { .. }), "{:?}", err);
+    // 损坏的压缩包被删除,下次重新下载
+    assert!(!ctx.paths.lobe_chat_archive.exists());
+    assert!(!ctx.paths.lobe_chat_dir.exists());
+    assert!(!install::staging_path(&ctx.paths.lobe_chat_dir).exists());
+    assert!(InstallManifest::read(dir.path(), Artifact::LobeChat).is_none());
+}
+
+#[tokio::test]
+async fn rejects_archive_with_mismatching_sha256() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve("/lobe-chat.zip", Fixture::new(common::lobe_chat_zip()));
+    let mut config = common::config(dir.path(), &url, &url);
+    config.lobe_chat.sha256 = Some("0".repeat(64));
+    let (ctx, _) = common::context(dir.path(), config);
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    match err {
+        Error::Checksum {
+            expected, actual, ..
+        } => {
+            assert_eq!(expected, "0".repeat(64));
+            assert_ne!(actual, expected);
+        }
+        err => panic!("expected checksum mismatch, got {:?}", err),
+    }
+    assert!(!ctx.paths.lobe_chat_archive.exists());
+    assert!(!download::partial_path(&ctx.paths.lobe_chat_archive).exists());
+    assert!(!ctx.paths.lobe_chat_dir.exists());
+    assert!(InstallManifest::read(dir.path(), Artifact::LobeChat).is_none());
+}
+
+#[tokio::test]
+async fn rejects_entries_outside_the_output_directory() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve("/lobe-chat.zip", Fixture::new(common::traversal_zip()));
+    let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+    let err = ctx
+        .install_lobe_chat(&CancelToken::default())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, Error::Extract { .. }), "{:?}", err);
+    assert!(!dir.path().join("escaped.txt").exists());
+    assert!(!ctx.paths.lobe_chat_dir.exists());
+    assert!(InstallManifest::read(dir.path(), Artifact::LobeChat).is_none());
+}
+
+#[tokio::test]
+async fn rejects_tar_entries_outside_the_output_directory() {
+    for kind in [ArchiveKind::TarGz, ArchiveKind::TarXz, ArchiveKind::TarZst] {
+        let server = FixtureServer::start();
+        let dir = TempDir::new();
+        let path = format!("/lobe-chat{}", common::suffix(kind));
+        let url = server.serve(&path, Fixture::new(common::traversal_tar(kind)));
+        let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+
+        let err = ctx
+            .install_lobe_chat(&CancelToken::default())
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&err, Error::Extract { message, .. } if message.contains("escapes")),
+            "{:?}",
+            err
+        );
+        assert!(!dir.path().join("escaped.txt").exists());
+        assert!(!ctx.paths.lobe_chat_dir.exists());
+        assert!(!ctx.paths.lobe_chat_archive.exists());
+        assert!(InstallManifest::read(dir.path(), Artifact::LobeChat).is_none());
+    }
+}
+
+#[tokio::test]
+async fn stops_when_cancelled() {
+    let server = FixtureServer::start();
+    let dir = TempDir::new();
+    let url = server.serve("/lobe-chat.zip", Fixture::new(common::lobe_chat_zip()));
+    let (ctx, _) = common::context(dir.path(), common::config(dir.path(), &url, &url));
+    let token = CancelToken::default();
+    token.cancel(true);
+
+    let err = ctx.install_lobe_chat(&token).await.unwrap_err();
+
+    assert!(matches!(err, Error::Cancelled(_)), "{:?}", err);
+    assert!(!ctx.paths.lobe_chat_archive.exists());
+    assert!(!ctx.paths.lobe_chat_dir.exists());
+}
+
+#[test]
+fn adopts_lobe_chat_without_manifest() {
+    let dir = TempDir::new();
+    let lobe_chat_dir = dir.path().join("lobe-chat_1.0.0");
+    fs::create_dir_all(&lobe_chat_dir).unwrap();
+    fs::write(
+        lobe_chat_dir.join(install::LOBE_CHAT_ENTRY),
+        common::SERVER_ENTRY,
+    )
+    .unwrap();
+
+    assert!(install::check_lobe_chat(
+        dir.path(),
+        &lobe_chat_dir,
+        "1.0.0"
+    ));
+    assert!(lobe_chat_dir.join(install::LOBE_CHAT_ENTRY).is_file());
+    let manifest = InstallManifest::read(dir.path(), Artifact::LobeChat).unwrap();
+    assert_eq!(manifest.version, "1.0.0");
+    assert!(manifest.sha256.is_none());
+}
+
+#[test]
+fn removes_lobe_chat_without_entry() {
+    let dir = TempDir::new();
+    let lobe_chat_dir = dir.path().join("lobe-chat_1.0.0");
+    fs::create_dir_all(lobe_chat_dir.join("public")).unwrap();
+    InstallManifest::new(Artifact::LobeChat, "1.0.0", None, None)
+        .save(dir.path())
+        .unwrap();
+
+    assert!(!install::check_lobe_chat(
+        dir.path(),
+        &lobe_chat_dir,
+        "1.0.0"
+    ));
+    assert!(!lobe_chat_dir.exists());
+    assert!(InstallManifest::read(dir.path(), Artifact::LobeChat).is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn checks_installed_deno_version() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let deno_bin = dir.path().join("deno");
+    fs::write(
+        &deno_bin,
+        "#!/bin/sh\necho \"deno 1.46.3 (stable, release)\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&deno_bin, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // 用户自己安装的 deno,版本一致时补上记录
+    assert!(install::check_deno(dir.path(), &deno_bin, "1.46.3"));
+    let manifest = InstallManifest::read(dir.path(), Artifact::Deno).unwrap();
+    assert_eq!(manifest.version, "1.46.3");
+
+    // 修改配置的版本后需要重新安装,已有的 deno 留到安装时覆盖
+    assert!(!install::check_deno(
+        dir.path(),
+        &deno_bin,
+        common::DENO_VERSION
+    ));
+    assert!(deno_bin.is_file());
+    assert!(InstallManifest::read(dir.path(), Artifact::Deno).is_none());
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::fs;
+
+use common::TempDir;
+use lobe_chat_lib::config::CONFIG_FILENAME;
+use lobe_chat_lib::migrations::{migrate_config, MigrationError};
+
+#[test]
+fn skips_missing_config() {
+    let dir = TempDir::new();
+    assert!(migrate_config(dir.path()).unwrap().is_none());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn backs_up_config_before_migrating() {
+    let dir = TempDir::new();
+    let path = dir.path().join(CONFIG_FILENAME);
+    let original = "[server]\nport = 3210\n";
+    fs::write(&path, original).unwrap();
+    let outcome = migrate_config(dir.path()).unwrap().unwrap();
+    assert_eq!(outcome.from, 0);
+    assert_eq!(outcome.to, 1);
+    assert_eq!(
+        outcome.backup,
+        dir.path().join(format!("{}.v0.bak", CONFIG_FILENAME))
+    );
+    assert_eq!(fs::read_to_string(&outcome.backup).unwrap(), original);
+    let migrated: toml::Table = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(migrated["schema_version"].as_integer(), Some(1));
+    assert_eq!(migrated["server"]["port"].as_integer(), Some(3210));
+    // 已是最新版本时不再迁移
+    assert!(migrate_config(dir.path()).unwrap().is_none());
+}
+
+#[test]
+fn rejects_config_from_newer_client() {
+    let dir = TempDir::new();
+    let path = dir.path().join(CONFIG_FILENAME);
+    let content = "schema_version = 2\n";
+    fs::write(&path, content).unwrap();
+    match migrate_config(dir.path()) {
+        Err(MigrationError::TooNew {
+            found, supported, ..
+        }) => {
+            assert_eq!(found, 2);
+            assert_eq!(supported, 1);
+        }
+        r => panic!("expected TooNew, got {:?}", r),
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), content);
+}
+
+#[test]
+fn rejects_corrupt_config() {
+    let dir = TempDir::new();
+    let path = dir.path().join(CONFIG_FILENAME);
+    for content in [
+        "[server\n",
+        "schema_version = -1\n",
+        "schema_version = \"1\"\n",
+    ] {
+        fs::write(&path, content).unwrap();
+        assert!(matches!(
+            migrate_config(dir.path()),
+            Err(MigrationError::Corrupt { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+}
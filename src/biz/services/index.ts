@@ -2,12 +2,63 @@ import { request } from "@/biz/requests";
 
 /** 获取初始化信息 */
 export function fetchSetupConfig() {
-  return request.post<{ deno_bin: string; deno_existing: boolean; lobe_chat_dir: string; lobe_chat_existing: boolean }>(
+  return request.post<{
+    deno_bin: string;
+    deno_existing: boolean;
+    lobe_chat_dir: string;
+    lobe_chat_existing: boolean;
+    port: number;
+    /** 通过 --hidden 启动,不显示窗口 */
+    hidden: boolean;
+  }>(
     "fetch_setup_config",
     {}
   );
 }
 
+/** 下载进度,服务端没有返回文件大小时 total、percent、eta_secs 为 null */
+export type DownloadProgress = {
+  downloaded: number;
+  total: number | null;
+  percent: number | null;
+  /** 字节/秒 */
+  speed: number;
+  eta_secs: number | null;
+};
+
+/** 下载失败后准备重试,delay 单位为毫秒 */
+export type DownloadRetrying = {
+  url: string;
+  attempt: number;
+  max_retries: number;
+  delay: number;
+  reason: { code: string; kind: string; message: string; context: unknown };
+};
+
+/** 解压进度,tar 包无法预先知道文件数量,entries_total 为 null */
+export type ExtractProgress = {
+  file: string;
+  entries_done: number;
+  entries_total: number | null;
+  bytes_done: number;
+};
+
+export type SetupPhase =
+  | { phase: "checking_runtime" }
+  | ({ phase: "downloading_runtime"; url: string; target: string } & DownloadProgress)
+  | { phase: "extracting"; file: string }
+  | ({ phase: "downloading_bundle"; url: string; target: string } & DownloadProgress)
+  | { phase: "starting_server" }
+  | { phase: "ready"; url: string }
+  | { phase: "failed"; error: { code: string; kind: string; message: string; context: unknown } };
+
+/**
+ * 获取当前的初始化阶段,页面刷新后用于恢复进度
+ */
+export function fetchSetupStatus() {
+  return request.post<SetupPhase>("get_setup_status", {});
+}
+
 /**
  * 下载 deno 并配置环境变量
  */
@@ -24,9 +75,12 @@ export function downloadLobeChatBundle() {
 
 /**
  * 启动一个 pty
+ * 不传参数时使用 $SHELL 或用户设置中的 shell
  */
-export function startPTY() {
-  return request.post("start_pty", {});
+export function startPTY(
+  body: Partial<{ shell: string; args: string[]; cwd: string; env: Record<string, string> }> = {}
+) {
+  return request.post("start_pty", body);
 }
 
 /**
@@ -43,7 +97,57 @@ export function execute(params: string) {
 }
 
 /**
+ * 关闭初始化窗口并在主窗口中打开 LobeChat
+ * 主窗口只能打开本地服务和白名单中的地址,其他链接用系统浏览器打开
  */
 export function showLobeChatWindow(body: { url: string }) {
-  return request.post<void>("set_complete", body);
+  return request.post<void>("show_main_window", body);
+}
+
+/**
+ * 获取客户端设置
+ */
+export function fetchSettings() {
+  return request.post<Record<string, unknown>>("get_settings", {});
+}
+
+/**
+ * 修改客户端设置,只需要传入变化的字段
+ */
+export function updateSettings(patch: Record<string, unknown>) {
+  return request.post<{ changed: string[]; restart_required: string[] }>("update_settings", { patch });
+}
+
+export type StartupFailure = {
+  step: string;
+  error: { code: string; kind: string; message: string; context: unknown };
+};
+
+/**
+ * 获取启动过程中失败的步骤
+ */
+export function fetchStartupReport() {
+  return request.post<{ failures: StartupFailure[] }>("get_startup_report", {});
+}
+
+/**
+ * 重新启动客户端
+ */
+export function retryStartup() {
+  return request.post<void>("retry_startup", {});
+}
+
+/**
+ * 打开应用目录
+ */
+export function openAppDir() {
+  return request.post<void>("open_app_dir", {});
+}
+
+/**
+ * 取消下载
+ * @param body.keep_partial 是否保留已下载的部分,不传时使用设置中的值
+ */
+export function cancelDownload(body: { kind: "deno" | "lobe_chat"; keep_partial?: boolean }) {
+  return request.post<void>("cancel_download", body);
 }
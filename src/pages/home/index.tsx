@@ -7,25 +7,33 @@ import "@xterm/xterm/css/xterm.css";
 import { FitAddon } from "@xterm/addon-fit";
 import { CanvasAddon } from "@xterm/addon-canvas";
 import { WebLinksAddon } from "@xterm/addon-web-links";
-import { type } from "@tauri-apps/plugin-os";
 import { listen } from "@tauri-apps/api/event";
 import { getCurrentWindow } from "@tauri-apps/api/window";
-import { WebviewWindow, getCurrentWebviewWindow } from "@tauri-apps/api/webviewWindow";
 
 import { ViewComponent, ViewComponentProps } from "@/store/types";
 import { base, Handler } from "@/domains/base";
 import { RequestCore } from "@/domains/request";
 import { execute } from "@/biz/requests";
 import {
+  cancelDownload,
   downloadDeno,
   downloadLobeChatBundle,
+  DownloadProgress,
+  DownloadRetrying,
+  ExtractProgress,
   fetchSetupConfig,
+  fetchSetupStatus,
+  fetchStartupReport,
+  openAppDir,
   resizePTYWindowSize,
+  retryStartup,
+  SetupPhase,
+  StartupFailure,
   showLobeChatWindow,
   startPTY,
 } from "@/biz/services";
 import { debounce } from "@/utils/lodash/debounce";
-import { sleep } from "@/utils";
+import { bytes_to_size, seconds_to_hour, sleep } from "@/utils";
 import { Check, Info, Loader } from "lucide-solid";
 
 enum LobeChatSteps {
@@ -56,6 +64,10 @@ function HomeIndexPageCore(props: ViewComponentProps) {
     downloadLobeChat: new RequestCore(downloadLobeChatBundle),
     /** 展示 LobeChat 窗口 */
     showLobeChat: new RequestCore(showLobeChatWindow),
+    /** 获取启动失败的步骤 */
+    fetchStartupReport: new RequestCore(fetchStartupReport),
+    /** 获取当前的初始化阶段 */
+    fetchSetupStatus: new RequestCore(fetchSetupStatus),
   };
   const _pty_state = {
     initial: false,
@@ -70,6 +82,9 @@ function HomeIndexPageCore(props: ViewComponentProps) {
   let _deno = {
     existing: false,
     percent: 0,
+    progress: "",
+    /** 最近一次重试的原因 */
+    retrying: "",
     installed: false,
     messages: [] as string[],
     error: null as null | Error,
@@ -77,6 +92,8 @@ function HomeIndexPageCore(props: ViewComponentProps) {
   let _lobe_chat = {
     existing: false,
     percent: 0,
+    progress: "",
+    retrying: "",
     downloaded: false,
     messages: [] as string[],
     error: null as null | Error,
@@ -84,9 +101,16 @@ function HomeIndexPageCore(props: ViewComponentProps) {
   let _server = {
     messages: [] as string[],
   };
+  /** 启动失败的步骤 */
+  let _startup = {
+    failures: [] as StartupFailure[],
+  };
   const _config: Partial<{
     bin_path: string;
     lobe_chat_path: string;
+    port: number;
+    /** 后台启动,不显示窗口 */
+    hidden: boolean;
     // app_dir: string;
     // lobe_chat_repo_dir: string;
     // lobe_chat_build_dir: string;
@@ -134,67 +158,90 @@ function HomeIndexPageCore(props: ViewComponentProps) {
   const fitAddon = new FitAddon();
   term.loadAddon(fitAddon);
 
-  function getTermLines(messages: string[], range: number[]) {
-    const lines: string[] = [];
-    for (let i = range[0]; i < range[1] + 1; i += 1) {
-      const text = messages[i];
-      if (text) {
-        lines.push(text);
-      }
+  const handle_output = debounce(800, async () => {
+    bus.emit(Events.Change, { ..._state });
+  });
+  function showLobeChat(url: string) {
+    _step = LobeChatSteps.PrepareShowLobeChat;
+    bus.emit(Events.Change, { ..._state });
+    // 由客户端关闭初始化窗口并创建主窗口,后台启动时不显示
+    requests.showLobeChat.run({ url });
+  }
+  /** 下载进度的文字描述,没有文件大小时只展示已下载的大小 */
+  function formatProgress(progress: DownloadProgress) {
+    const size =
+      progress.total === null
+        ? bytes_to_size(progress.downloaded)
+        : `${bytes_to_size(progress.downloaded)} / ${bytes_to_size(progress.total)}`;
+    const texts = [size, `${bytes_to_size(progress.speed)}/s`];
+    if (progress.eta_secs !== null) {
+      texts.push(`${seconds_to_hour(progress.eta_secs)} left`);
     }
-    return lines;
+    return texts.join(" · ");
   }
-  async function startLobeChatServer(config: { lobe_chat_path: string; bin_path: string }) {
-    // console.log("[PAGE]home/index - startLobeChatServer", _config.lobe_chat_build_dir);
-    const PORT = 6188;
-    const env_command = type() === "windows" ? `$env:PORT=${PORT};` : `PORT=${PORT}`;
-    await execute(`cd ${config.lobe_chat_path}\r`);
-    await execute(`${env_command} ${config.bin_path} run --allow-all server.cjs\r`);
-    _pty_state.step = 4;
+  /** 初始化失败的提示,磁盘空间不足时给出需要的空间 */
+  function formatSetupError(error: { code: string; message: string; context: unknown }) {
+    if (error.code === "insufficient_disk_space") {
+      const { path, required, available } = error.context as {
+        path: string;
+        required: number;
+        available: number;
+      };
+      return `磁盘空间不足,${path} 需要 ${bytes_to_size(required)},仅剩 ${bytes_to_size(available)}`;
+    }
+    return error.message;
   }
-  const handle_output = debounce(800, async () => {
-    bus.emit(Events.Change, { ..._state });
-    if (_pty_state.step === 4) {
-      _pty_state.range_end = _messages.length - 1;
-      const lines = getTermLines(_messages, [_pty_state.range_start, _pty_state.range_end]);
-      _pty_state.range_start = _pty_state.range_end;
-      const str_lines = lines.map((l) => l.trim()).filter(Boolean);
-      if (str_lines.length === 0) {
-        return;
-      }
-      const regex = /Local: {1,}(http[a-z0-9://]{1,})/;
-      let url = (() => {
-        const matched = str_lines.find((line) => {
-          return line.match(regex);
+  /** 根据 Rust 端的初始化阶段更新界面 */
+  function applySetupPhase(phase: SetupPhase) {
+    if (phase.phase === "checking_runtime") {
+      _step = LobeChatSteps.CheckDenoExisting;
+    }
+    if (phase.phase === "downloading_runtime") {
+      _step = LobeChatSteps.InstallDeno;
+      _deno.messages = [`url: ${phase.url}`, `download to: ${phase.target}`];
+      _deno.percent = phase.percent === null ? 0 : parseFloat(phase.percent.toFixed(2));
+      _deno.progress = formatProgress(phase);
+    }
+    if (phase.phase === "extracting") {
+      const target = _step === LobeChatSteps.InstallDeno ? _deno : _lobe_chat;
+      target.messages = [`unzip: ${phase.file}`];
+    }
+    if (phase.phase === "downloading_bundle") {
+      _step = LobeChatSteps.DownloadLobeChat;
+      _deno.existing = true;
+      _lobe_chat.messages = [`url: ${phase.url}`, `download to: ${phase.target}`];
+      _lobe_chat.percent = phase.percent === null ? 0 : parseFloat(phase.percent.toFixed(2));
+      _lobe_chat.progress = formatProgress(phase);
+    }
+    if (phase.phase === "starting_server") {
+      // 服务由客户端启动,这里只展示它的输出
+      _step = LobeChatSteps.StartLobeChatServer;
+      _deno.existing = true;
+      _lobe_chat.existing = true;
+      _pty_state.step = 4;
+    }
+    if (phase.phase === "ready") {
+      bus.emit(Events.Change, { ..._state });
+      showLobeChat(phase.url);
+      return;
+    }
+    if (phase.phase === "failed") {
+      const error = new Error(formatSetupError(phase.error));
+      if (_step === LobeChatSteps.InstallDeno) {
+        _step = LobeChatSteps.InstallDenoFailed;
+        _deno.error = error;
+      } else if (_step === LobeChatSteps.DownloadLobeChat) {
+        _step = LobeChatSteps.DownloadLobeChatFailed;
+        _lobe_chat.error = error;
+      } else {
+        _step = LobeChatSteps.SetupFailed;
+        app.tip({
+          text: ["初始化失败", error.message],
         });
-        if (!matched) {
-          return null;
-        }
-        const m2 = matched.match(regex);
-        if (m2) {
-          return m2[1];
-        }
-        return null;
-      })();
-      if (!url) {
-        return;
       }
-      _step = LobeChatSteps.PrepareShowLobeChat;
-      bus.emit(Events.Change, { ..._state });
-      const setup = getCurrentWebviewWindow();
-      setup.hide();
-      setTimeout(() => {
-        setup.close();
-      }, 1000);
-      const webview = new WebviewWindow("main", {
-        title: "LobeChatClient",
-        width: 1200,
-        height: 800,
-        url,
-      });
-      webview.show();
     }
-  });
+    bus.emit(Events.Change, { ..._state });
+  }
   const _state = {
     get step() {
       return _step;
@@ -208,6 +255,9 @@ function HomeIndexPageCore(props: ViewComponentProps) {
     get server() {
       return _server;
     },
+    get startup() {
+      return _startup;
+    },
   };
 
   enum Events {
@@ -237,73 +287,31 @@ function HomeIndexPageCore(props: ViewComponentProps) {
     // term.scrollToBottom();
     handle_output();
   });
-  listen<{ uri: string; target: string }>("deno_download_start", (event) => {
-    const data = event.payload;
-    console.log("[PAGE]home/index - handle deno_download_start", data);
-    _deno.messages.push(`url: ${data.uri}`);
-    _deno.messages.push(`download to: ${data.target}`);
-    bus.emit(Events.Change, { ..._state });
-  });
-  listen<{ uri: string; target: string }>("lobe_chat_download_start", (event) => {
-    const data = event.payload;
-    _lobe_chat.messages.push(`url: ${data.uri}`);
-    _lobe_chat.messages.push(`download to: ${data.target}`);
-    bus.emit(Events.Change, { ..._state });
+  listen<SetupPhase>("setup_phase_changed", (event) => {
+    console.log("[PAGE]home/index - setup_phase_changed", event.payload);
+    applySetupPhase(event.payload);
   });
-  listen<{ bin_path: string }>("can_download_lobe_chat", async (event) => {
-    console.log("[PAGE]home/index - can_download_lobe_chat", event.payload);
-    Object.assign(_config, event.payload);
-    _step = LobeChatSteps.DownloadLobeChat;
-    _deno.existing = true;
-    bus.emit(Events.Change, { ..._state });
-    await requests.downloadLobeChat.run();
-  });
-  listen<{ lobe_chat_path: string }>("can_start_lobe_chat_server", async (event) => {
-    console.log("[PAGE]home/index - can_start_lobe_chat_server", event.payload);
-    _step = LobeChatSteps.StartLobeChatServer;
-    _lobe_chat.existing = true;
-    bus.emit(Events.Change, { ..._state });
-    // const { lobe_chat_path } = event.payload;
-    Object.assign(_config, event.payload);
-    if (!_config.lobe_chat_path) {
-      app.tip({
-        text: ["缺少 lobe_chat 文件"],
-      });
-      return;
+  listen<ExtractProgress>("extract_progress", (event) => {
+    const { entries_done, entries_total, bytes_done } = event.payload;
+    const target = _step === LobeChatSteps.InstallDeno ? _deno : _lobe_chat;
+    if (entries_total === null) {
+      target.percent = 0;
+      target.progress = `unzip ${entries_done} files · ${bytes_to_size(bytes_done)}`;
+    } else {
+      target.percent = entries_total === 0 ? 0 : parseFloat(((entries_done / entries_total) * 100).toFixed(2));
+      target.progress = `unzip ${entries_done} / ${entries_total} files · ${bytes_to_size(bytes_done)}`;
     }
-    if (!_config.bin_path) {
-      app.tip({
-        text: ["缺少 deno 文件"],
-      });
-      return;
-    }
-    startLobeChatServer({
-      bin_path: _config.bin_path,
-      lobe_chat_path: _config.lobe_chat_path,
-    });
-  });
-  listen<{ reason: string; filepath: string }>("deno_download_failed", (event) => {
-    const data = event.payload;
-    console.log("[PAGE]home/index - deno_download_failed", data);
-    _step = LobeChatSteps.InstallDenoFailed;
-    _deno.error = new Error(`${data.reason} - ${data.filepath}`);
     bus.emit(Events.Change, { ..._state });
   });
-  listen<{ reason: string; filepath: string }>("lobe_chat_download_failed", (event) => {
-    const data = event.payload;
-    console.log("[PAGE]home/index - lobe_chat_download_failed", data);
-    _step = LobeChatSteps.DownloadLobeChatFailed;
-    _lobe_chat.error = new Error(`${data.reason} - ${data.filepath}`);
+  listen<DownloadRetrying>("download_retrying", (event) => {
+    const { attempt, max_retries, delay, reason } = event.payload;
+    const target = _step === LobeChatSteps.InstallDeno ? _deno : _lobe_chat;
+    target.retrying = `retry ${attempt}/${max_retries} in ${(delay / 1000).toFixed(1)}s: ${reason.message}`;
     bus.emit(Events.Change, { ..._state });
   });
-  listen<{ percent: number }>("deno_download_percent", (event) => {
-    const data = event.payload;
-    _deno.percent = parseFloat(data.percent.toFixed(2));
-    bus.emit(Events.Change, { ..._state });
-  });
-  listen<{ percent: number }>("lobe_chat_download_percent", (event) => {
-    const data = event.payload;
-    _lobe_chat.percent = parseFloat(data.percent.toFixed(2));
+  listen<{ failures: StartupFailure[] }>("startup_failed", (event) => {
+    _step = LobeChatSteps.SetupFailed;
+    _startup.failures = event.payload.failures;
     bus.emit(Events.Change, { ..._state });
   });
   listen("tauri://close-requested", (event) => {
@@ -320,6 +328,13 @@ function HomeIndexPageCore(props: ViewComponentProps) {
       //   return;
       // }
       // term.open($term);
+      const r0 = await requests.fetchStartupReport.run();
+      if (r0.data && r0.data.failures.length !== 0) {
+        _step = LobeChatSteps.SetupFailed;
+        _startup.failures = r0.data.failures;
+        bus.emit(Events.Change, { ..._state });
+        return;
+      }
       const r = await requests.startPTY.run();
       if (r.error) {
         app.tip({
@@ -342,6 +357,16 @@ function HomeIndexPageCore(props: ViewComponentProps) {
         return;
       }
       console.log("[PAGE]home/index - setup config", r3.data);
+      _config.port = r3.data.port;
+      _config.hidden = r3.data.hidden;
+      _config.bin_path = r3.data.deno_bin;
+      _config.lobe_chat_path = r3.data.lobe_chat_dir;
+      const r2 = await requests.fetchSetupStatus.run();
+      if (r2.data && r2.data.phase !== "checking_runtime" && r2.data.phase !== "failed") {
+        // 页面刷新时初始化仍在进行中,恢复进度即可
+        applySetupPhase(r2.data);
+        return;
+      }
       if (!r3.data.deno_existing) {
         _step = LobeChatSteps.InstallDeno;
         bus.emit(Events.Change, { ..._state });
@@ -353,30 +378,18 @@ function HomeIndexPageCore(props: ViewComponentProps) {
           app.tip({
             text: ["下载 deno 失败", r4.error.message],
           });
-          return;
         }
         return;
       }
       _deno.existing = true;
-      if (!r3.data.lobe_chat_existing) {
-        _step = LobeChatSteps.DownloadLobeChat;
+      _step = LobeChatSteps.DownloadLobeChat;
+      bus.emit(Events.Change, { ..._state });
+      const r4 = await requests.downloadLobeChat.run();
+      if (r4.error) {
+        _step = LobeChatSteps.DownloadLobeChatFailed;
+        _lobe_chat.error = r4.error;
         bus.emit(Events.Change, { ..._state });
-        const r4 = await requests.downloadLobeChat.run();
-        if (r4.error) {
-          _step = LobeChatSteps.DownloadLobeChatFailed;
-          _lobe_chat.error = r4.error;
-          bus.emit(Events.Change, { ..._state });
-          return;
-        }
-        return;
       }
-      _step = LobeChatSteps.StartLobeChatServer;
-      _lobe_chat.existing = true;
-      bus.emit(Events.Change, { ..._state });
-      startLobeChatServer({
-        bin_path: r3.data.deno_bin,
-        lobe_chat_path: r3.data.lobe_chat_dir,
-      });
     },
     destroy() {
       execute("\x03");
@@ -449,6 +462,20 @@ export const HomeIndexPage: ViewComponent = (props) => {
                     <div class="ml-1 text-gray-200">{state().deno.percent}%</div>
                   </div>
                 </Show>
+                <Show when={state().deno.progress}>
+                  <div class="mt-1 text-gray-400">{state().deno.progress}</div>
+                </Show>
+                <Show when={state().deno.retrying}>
+                  <div class="mt-1 break-all text-yellow-500">{state().deno.retrying}</div>
+                </Show>
+                <div
+                  class="mt-2 w-fit px-2 border border-gray-500 rounded cursor-pointer text-gray-300 hover:border-gray-300"
+                  onClick={() => {
+                    cancelDownload({ kind: "deno" });
+                  }}
+                >
+                  Cancel
+                </div>
               </div>
             </div>
           </Show>
@@ -487,6 +514,20 @@ export const HomeIndexPage: ViewComponent = (props) => {
                     <div class="ml-1 text-gray-200">{state().lobe_chat.percent}%</div>
                   </div>
                 </Show>
+                <Show when={state().lobe_chat.progress}>
+                  <div class="mt-1 text-gray-400">{state().lobe_chat.progress}</div>
+                </Show>
+                <Show when={state().lobe_chat.retrying}>
+                  <div class="mt-1 break-all text-yellow-500">{state().lobe_chat.retrying}</div>
+                </Show>
+                <div
+                  class="mt-2 w-fit px-2 border border-gray-500 rounded cursor-pointer text-gray-300 hover:border-gray-300"
+                  onClick={() => {
+                    cancelDownload({ kind: "lobe_chat" });
+                  }}
+                >
+                  Cancel
+                </div>
               </div>
             </div>
           </Show>
@@ -537,6 +578,35 @@ export const HomeIndexPage: ViewComponent = (props) => {
                 <div>Setup failed.</div>
               </div>
             </div>
+            <div class="mt-2 space-y-1 text-gray-300">
+              <For each={state().startup.failures}>
+                {(failure) => {
+                  return (
+                    <div class="break-all">
+                      [{failure.step}] {failure.error.message}
+                    </div>
+                  );
+                }}
+              </For>
+            </div>
+            <div class="mt-4 flex items-center space-x-4 text-white">
+              <div
+                class="px-2 py-1 border border-gray-500 rounded cursor-pointer hover:border-gray-300"
+                onClick={() => {
+                  retryStartup();
+                }}
+              >
+                Retry
+              </div>
+              <div
+                class="px-2 py-1 border border-gray-500 rounded cursor-pointer hover:border-gray-300"
+                onClick={() => {
+                  openAppDir();
+                }}
+              >
+                Open app directory
+              </div>
+            </div>
           </Show>
         </div>
       </div>